@@ -0,0 +1,596 @@
+use core::animation::{Keyframe, DEFAULT_WIDTH};
+use core::color::Palette;
+use core::dither::Dither;
+use core::hdr::ToneMap;
+use core::kf;
+use core::location::Location;
+use core::raw::Channel;
+use core::y4m::{Matrix, Subsampling};
+use core::{Dim, Fractal};
+use std::fs;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: mandelbrot [OPTIONS] -o <FILE>
+       mandelbrot [OPTIONS] --keyframes <FILE> -o <DIR>
+       mandelbrot [OPTIONS] --exp-map <ZOOM> -o <DIR>
+       mandelbrot [OPTIONS] --zoom-sequence <ZOOM> -o <DIR>
+       mandelbrot [OPTIONS] --data <CHANNEL> -o <FILE>.npy|png|pfm
+       mandelbrot [OPTIONS] --recolor <FILE> -o <FILE>
+       mandelbrot [OPTIONS] --palette-loop <FRAMES> -o <FILE>.gif
+       mandelbrot [OPTIONS] --open <FILE>.png|toml|json|kfr -o <FILE>
+       mandelbrot [OPTIONS] --save-location <FILE>.toml|json|kfr|kfp
+       mandelbrot [OPTIONS] --keyframes <FILE> -o - | ffmpeg -i - -colorspace bt709 video.mp4
+
+Options:
+  -o, --output <FILE>        Image to write, animations also go into a y4m video file or to stdout with `-`
+      --open <FILE>          Start from the view and settings of a png we rendered, of a location
+                             file or of a Kalles Fraktaler location, other options change them
+      --kf-palette <FILE>    Colors from a Kalles Fraktaler palette, replaced by the closest palette
+      --save-location <FILE> Save the view and settings as TOML or JSON location file, or as Kalles
+                             Fraktaler location or palette, renders nothing without --output
+  -c, --center <RE,IM>       Center of the view [default: -0.765,0]
+  -z, --zoom <ZOOM>          Zoom relative to the whole set [default: 1]
+      --x-range <MIN,MAX>    Real range, replaces center and zoom
+      --y-range <MIN,MAX>    Imaginary range, replaces center and zoom
+  -s, --size <WxH>           Image size in pixels [default: 1920x1080]
+  -i, --iterations <N>       Max iterations [default: 1000]
+  -f, --fractal <NAME>       mandelbrot, burning-ship [default: mandelbrot]
+  -p, --palette <NAME>       rainbow, fire, grayscale [default: rainbow]
+      --tone-map <NAME>      clamp, reinhard, aces, for 8 bit images [default: clamp]
+      --exposure <STOPS>     Brightness change before the tone mapping [default: 0]
+      --dither <NAME>        none, blue-noise, floyd-steinberg, for 8 bit images [default: none]
+      --format <FORMAT>      png, ppm, hdr, gif, dzi, xyz, y4m [default: from the file extension]
+      --tile-size <N>        Tile size of dzi and xyz pyramids [default: 256]
+      --overlap <N>          Overlap of dzi and xyz tiles [default: 0]
+      --keyframes <FILE>     Render an animation into a directory of numbered frames, one
+                             keyframe per line: <RE>,<IM> <ZOOM> [ROTATION] [ITERATIONS] [PALETTE OFFSET]
+                             with the rotation in degrees, an unfinished animation is resumed
+      --exp-map <ZOOM>       Zoom from the view into its center up to this zoom, rendered as a single
+                             log-polar strip that gets unwrapped into the frames
+      --zoom-sequence <ZOOM> Zoom from the view into its center up to this zoom, with one keyframe
+                             per halving of the zoom and the frames in between blended from them
+      --frames <N>           Frames of the animation [default: 300]
+      --data <CHANNEL>       Save raw values instead of colors: iterations, smooth, distance,
+                             as npy array, 16 bit grayscale png or pfm float image
+      --recolor <FILE>       Color the values of a saved data file with the palette
+      --palette-loop <FRAMES>
+                             Cycle the colors once through the palette in a looping gif or animated png
+      --fps <N>              Frame rate of y4m videos and palette loops [default: 30]
+      --matrix <NAME>        YUV matrix of y4m videos: bt601, bt709 [default: bt709]
+      --chroma <SUBSAMPLING> Chroma subsampling of y4m videos: 420, 444 [default: 420]
+  -h, --help                 Print this help";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Ppm,
+    ///Radiance image with linear colors that can go beyond 1
+    Hdr,
+    ///Only for palette loops
+    Gif,
+    ///Deep Zoom tile pyramid, the output is the `.dzi` file
+    Dzi,
+    ///`z/x/y.png` tile pyramid, the output is a directory
+    Xyz,
+    ///Uncompressed YUV video of an animation, the output is a file or `-` for stdout
+    Y4m,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "png" => Some(Format::Png),
+            "ppm" => Some(Format::Ppm),
+            "hdr" => Some(Format::Hdr),
+            "gif" => Some(Format::Gif),
+            "dzi" => Some(Format::Dzi),
+            "xyz" => Some(Format::Xyz),
+            "y4m" => Some(Format::Y4m),
+            _ => None,
+        }
+    }
+}
+
+pub struct Args {
+    ///Empty if only the location gets saved
+    pub output: PathBuf,
+    ///Location file to write
+    pub save_location: Option<PathBuf>,
+    ///Settings of opened files that can't be used
+    pub warnings: Vec<String>,
+    ///Center and zoom as given, the ranges are calculated from them
+    pub center: (f64, f64),
+    pub zoom: f64,
+    pub x_range: (f64, f64),
+    pub y_range: (f64, f64),
+    pub size: (usize, usize),
+    pub iterations: u64,
+    pub radius: f64,
+    ///Counterclockwise rotation in radians
+    pub rotation: f64,
+    pub fractal: Fractal,
+    pub palette: Palette,
+    pub palette_offset: f32,
+    pub format: Format,
+    pub tone_map: ToneMap,
+    ///Stops added before the tone mapping
+    pub exposure: f32,
+    pub dither: Dither,
+    pub tile_size: usize,
+    pub overlap: usize,
+    pub keyframes: Option<Vec<Keyframe>>,
+    ///End zoom of an exponential map animation
+    pub exp_map: Option<f64>,
+    ///End zoom of a zoom sequence
+    pub zoom_sequence: Option<f64>,
+    pub frames: usize,
+    ///Raw values to save instead of colors
+    pub data: Option<Channel>,
+    ///Data file to color instead of rendering
+    pub recolor: Option<PathBuf>,
+    ///Frames of a palette cycling loop
+    pub palette_loop: Option<usize>,
+    pub fps: u32,
+    pub matrix: Matrix,
+    pub subsampling: Subsampling,
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, name: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid {name} '{value}'"))
+}
+
+///Two numbers separated by the given character, like `-0.5,0.1` or `1920x1080`
+fn parse_pair<T: std::str::FromStr>(
+    value: &str,
+    separator: char,
+    name: &str,
+) -> Result<(T, T), String> {
+    let (a, b) = value.split_once(separator).ok_or_else(|| {
+        format!("Invalid {name} '{value}', expected two values separated by '{separator}'")
+    })?;
+
+    Ok((parse_number(a, name)?, parse_number(b, name)?))
+}
+
+///Real and imaginary part of a point, both have to be finite
+fn parse_center(value: &str) -> Result<(f64, f64), String> {
+    let center: (f64, f64) = parse_pair(value, ',', "center")?;
+    if !center.0.is_finite() || !center.1.is_finite() {
+        return Err(format!("Invalid center '{value}', expected finite numbers"));
+    }
+    Ok(center)
+}
+
+///Zoom relative to the whole set, it has to be finite and larger than zero
+fn parse_zoom(value: &str) -> Result<f64, String> {
+    let zoom: f64 = parse_number(value, "zoom")?;
+    if !(zoom > 0. && zoom.is_finite()) {
+        return Err(format!(
+            "Invalid zoom '{value}', expected a number larger than zero"
+        ));
+    }
+    Ok(zoom)
+}
+
+///Whether a range is finite and has its minimum first
+fn is_range((min, max): (f64, f64)) -> bool {
+    min.is_finite() && max.is_finite() && min < max
+}
+
+///One keyframe per line, empty lines and lines starting with `#` are skipped
+fn parse_keyframes(text: &str) -> Result<Vec<Keyframe>, String> {
+    let mut keyframes = Vec::new();
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let values: Vec<&str> = line.split_whitespace().collect();
+        if values.len() < 2 || values.len() > 5 {
+            return Err(format!("Invalid keyframe '{line}'"));
+        }
+
+        let mut keyframe = Keyframe::new(parse_center(values[0])?, parse_zoom(values[1])?);
+        if let Some(rotation) = values.get(2) {
+            keyframe = keyframe.rotation(parse_number::<f64>(rotation, "rotation")?.to_radians());
+        }
+        if let Some(iterations) = values.get(3) {
+            keyframe = keyframe.iterations(parse_number(iterations, "iterations")?);
+        }
+        if let Some(offset) = values.get(4) {
+            keyframe = keyframe.palette_offset(parse_number(offset, "palette offset")?);
+        }
+
+        keyframes.push(keyframe);
+    }
+
+    if keyframes.is_empty() {
+        return Err("The keyframe file has no keyframes".to_string());
+    }
+    Ok(keyframes)
+}
+
+impl Args {
+    ///Parse the arguments without the program name, `Err` contains the message for the user
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        //Options that default to the opened location
+        let mut output = None;
+        let mut open = None;
+        let mut save_location = None;
+        let mut kf_palette = None;
+        let mut center = None;
+        let mut zoom = None;
+        let mut x_range = None;
+        let mut y_range = None;
+        let mut size = None;
+        let mut iterations = None;
+        let mut fractal = None;
+        let mut palette = None;
+        let mut format = None;
+        let mut tone_map = None;
+        let mut exposure = None;
+        let mut dither = None;
+        let mut tile_size = 256;
+        let mut overlap = 0;
+        let mut keyframes = None;
+        let mut exp_map = None;
+        let mut zoom_sequence = None;
+        let mut frames = 300;
+        let mut data = None;
+        let mut recolor = None;
+        let mut palette_loop = None;
+        let mut fps = 30;
+        let mut matrix = Matrix::Bt709;
+        let mut subsampling = Subsampling::Yuv420;
+
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(String::new());
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for '{arg}'"))?;
+
+            match arg.as_str() {
+                "-o" | "--output" => output = Some(PathBuf::from(value)),
+                "--open" => open = Some(PathBuf::from(value)),
+                "--save-location" => save_location = Some(PathBuf::from(value)),
+                "--kf-palette" => kf_palette = Some(PathBuf::from(value)),
+                "-c" | "--center" => center = Some(parse_center(&value)?),
+                "-z" | "--zoom" => zoom = Some(parse_zoom(&value)?),
+                "--x-range" => x_range = Some(parse_pair(&value, ',', "x range")?),
+                "--y-range" => y_range = Some(parse_pair(&value, ',', "y range")?),
+                "-s" | "--size" => size = Some(parse_pair(&value, 'x', "size")?),
+                "-i" | "--iterations" => iterations = Some(parse_number(&value, "iterations")?),
+                "-f" | "--fractal" => {
+                    fractal = Some(
+                        Fractal::from_name(&value)
+                            .ok_or_else(|| format!("Unknown fractal '{value}'"))?,
+                    )
+                }
+                "-p" | "--palette" => {
+                    palette = Some(
+                        Palette::from_name(&value)
+                            .ok_or_else(|| format!("Unknown palette '{value}'"))?,
+                    )
+                }
+                "--format" => {
+                    format = Some(
+                        Format::from_name(&value)
+                            .ok_or_else(|| format!("Unknown format '{value}'"))?,
+                    )
+                }
+                "--tone-map" => {
+                    tone_map = Some(
+                        ToneMap::from_name(&value)
+                            .ok_or_else(|| format!("Unknown tone map '{value}'"))?,
+                    )
+                }
+                "--exposure" => exposure = Some(parse_number(&value, "exposure")?),
+                "--dither" => {
+                    dither = Some(
+                        Dither::from_name(&value)
+                            .ok_or_else(|| format!("Unknown dithering '{value}'"))?,
+                    )
+                }
+                "--tile-size" => tile_size = parse_number(&value, "tile size")?,
+                "--overlap" => overlap = parse_number(&value, "overlap")?,
+                "--keyframes" => {
+                    let text = fs::read_to_string(&value)
+                        .map_err(|err| format!("Failed to read '{value}': {err}"))?;
+                    keyframes = Some(parse_keyframes(&text)?);
+                }
+                "--exp-map" => exp_map = Some(parse_zoom(&value)?),
+                "--zoom-sequence" => zoom_sequence = Some(parse_zoom(&value)?),
+                "--frames" => frames = parse_number(&value, "frames")?,
+                "--data" => {
+                    data = Some(
+                        Channel::from_name(&value)
+                            .ok_or_else(|| format!("Unknown channel '{value}'"))?,
+                    )
+                }
+                "--recolor" => recolor = Some(PathBuf::from(value)),
+                "--palette-loop" => palette_loop = Some(parse_number(&value, "frames")?),
+                "--fps" => fps = parse_number(&value, "frame rate")?,
+                "--matrix" => {
+                    matrix = Matrix::from_name(&value)
+                        .ok_or_else(|| format!("Unknown matrix '{value}'"))?
+                }
+                "--chroma" => {
+                    subsampling = Subsampling::from_name(&value)
+                        .ok_or_else(|| format!("Unknown chroma subsampling '{value}'"))?
+                }
+                _ => return Err(format!("Unknown argument '{arg}'")),
+            }
+        }
+
+        let output = match output {
+            Some(output) => output,
+            None if save_location.is_some() => PathBuf::new(),
+            None => return Err("Missing output file".to_string()),
+        };
+
+        //The image size of an opened png is part of its view, location files may have one
+        let failed = |path: &PathBuf, err| format!("Failed to open '{}': {err}", path.display());
+        let default_size = size.map_or(Dim::new(1920, 1080), |(x, y)| Dim::new(x, y));
+        let mut warnings = Vec::new();
+        let (mut location, image_size) = match &open {
+            Some(path) => match kf::Kind::from_path(path) {
+                //The zoom of Kalles Fraktaler depends on the image size
+                Some(kf::Kind::Location) => {
+                    let (location, kf_warnings) =
+                        kf::load_location(path, default_size).map_err(|err| failed(path, err))?;
+                    warnings.extend(kf_warnings);
+                    (location, None)
+                }
+                Some(kf::Kind::Palette) => {
+                    return Err("Kalles Fraktaler palettes are opened with --kf-palette".to_string())
+                }
+                None => Location::open(path).map_err(|err| failed(path, err))?,
+            },
+            None => (Location::default(), None),
+        };
+        if let Some(path) = &kf_palette {
+            warnings
+                .extend(kf::load_palette(path, &mut location).map_err(|err| failed(path, err))?);
+        }
+        let image_size = image_size.unwrap_or(default_size);
+        let center = center.unwrap_or(location.center);
+        let zoom = zoom.unwrap_or(location.zoom);
+        let size = size.unwrap_or((image_size.x, image_size.y));
+        let iterations = iterations.unwrap_or(location.iterations);
+        let fractal = fractal.unwrap_or(location.fractal);
+        let palette = palette.unwrap_or(location.palette);
+        let tone_map = tone_map.unwrap_or(location.tone_mapper.tone_map);
+        let exposure = exposure.unwrap_or(location.tone_mapper.exposure);
+        let dither = dither.unwrap_or(location.tone_mapper.dither);
+
+        if size.0 == 0 || size.1 == 0 {
+            return Err("Image size can't be zero".to_string());
+        }
+        if tile_size == 0 {
+            return Err("Tile size can't be zero".to_string());
+        }
+        if fps == 0 {
+            return Err("Frame rate can't be zero".to_string());
+        }
+        if frames == 0 || palette_loop == Some(0) {
+            return Err("Frames can't be zero".to_string());
+        }
+        //Opened locations are not checked while they are read
+        if !(zoom > 0. && zoom.is_finite() && center.0.is_finite() && center.1.is_finite()) {
+            return Err("The view needs a finite center and a zoom larger than zero".to_string());
+        }
+
+        //Square pixels around the center
+        let (center, zoom, x_range, y_range) = match (x_range, y_range) {
+            (Some(x_range), Some(y_range)) if !is_range(x_range) || !is_range(y_range) => {
+                return Err("Ranges need finite numbers with the minimum first".to_string())
+            }
+            (Some(x_range), Some(y_range)) => (
+                ((x_range.0 + x_range.1) / 2., (y_range.0 + y_range.1) / 2.),
+                DEFAULT_WIDTH / (x_range.1 - x_range.0),
+                x_range,
+                y_range,
+            ),
+            (None, None) => {
+                let width = DEFAULT_WIDTH / zoom;
+                let height = width * size.1 as f64 / size.0 as f64;
+
+                (
+                    center,
+                    zoom,
+                    (center.0 - width / 2., center.0 + width / 2.),
+                    (center.1 - height / 2., center.1 + height / 2.),
+                )
+            }
+            _ => return Err("--x-range and --y-range have to be used together".to_string()),
+        };
+
+        let stdout = output.as_os_str() == "-";
+        let format = match format {
+            Some(format) => format,
+            None if stdout => Format::Y4m,
+            None => output
+                .extension()
+                .and_then(|ext| Format::from_name(&ext.to_string_lossy().to_lowercase()))
+                .unwrap_or(Format::Png),
+        };
+
+        let animation = keyframes.is_some() || exp_map.is_some() || zoom_sequence.is_some();
+        if format == Format::Y4m && !animation {
+            return Err(
+                "y4m needs an animation from --keyframes, --exp-map or --zoom-sequence".to_string(),
+            );
+        }
+        if format == Format::Gif && palette_loop.is_none() {
+            return Err("gif needs a palette loop from --palette-loop".to_string());
+        }
+        if palette_loop.is_some() && !matches!(format, Format::Png | Format::Gif) {
+            return Err("Palette loops are saved as png or gif".to_string());
+        }
+        if stdout && format != Format::Y4m {
+            return Err("Only y4m videos can be written to stdout".to_string());
+        }
+
+        Ok(Self {
+            output,
+            save_location,
+            warnings,
+            center,
+            zoom,
+            x_range,
+            y_range,
+            size,
+            iterations,
+            radius: location.radius,
+            rotation: location.rotation,
+            fractal,
+            palette,
+            palette_offset: location.palette_offset,
+            format,
+            tone_map,
+            exposure,
+            dither,
+            tile_size,
+            overlap,
+            keyframes,
+            exp_map,
+            zoom_sequence,
+            frames,
+            data,
+            recolor,
+            palette_loop,
+            fps,
+            matrix,
+            subsampling,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        Args::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn defaults() {
+        let args = parse("-o out.png").unwrap();
+        assert_eq!(args.output, PathBuf::from("out.png"));
+        assert_eq!(args.format, Format::Png);
+        assert_eq!(args.size, (1920, 1080));
+        assert_eq!(
+            args.x_range,
+            (-0.765 - DEFAULT_WIDTH / 2., -0.765 + DEFAULT_WIDTH / 2.)
+        );
+        assert_eq!(args.iterations, 1000);
+        assert_eq!(args.fractal, Fractal::Mandelbrot);
+        assert_eq!(args.palette, Palette::Rainbow);
+    }
+
+    #[test]
+    fn ranges_around_the_center() {
+        let args = parse("-o a.png -c -0.5,0.25 -z 2 -s 200x100").unwrap();
+        let width = DEFAULT_WIDTH / 2.;
+
+        assert_eq!(args.x_range, (-0.5 - width / 2., -0.5 + width / 2.));
+        assert_eq!(args.y_range, (0.25 - width / 4., 0.25 + width / 4.));
+    }
+
+    #[test]
+    fn ranges_replace_center_and_zoom() {
+        let args = parse("-o a.png --x-range -2,1 --y-range -1,2").unwrap();
+        assert_eq!((args.x_range, args.y_range), ((-2., 1.), (-1., 2.)));
+
+        assert!(parse("-o a.png --x-range -2,1").is_err());
+    }
+
+    #[test]
+    fn options() {
+        let args = parse("--output a.ppm -i 50 -f burning-ship -p fire --size 3x4").unwrap();
+        assert_eq!(args.format, Format::Ppm);
+        assert_eq!(args.iterations, 50);
+        assert_eq!(args.fractal, Fractal::BurningShip);
+        assert_eq!(args.palette, Palette::Fire);
+        assert_eq!(args.size, (3, 4));
+
+        assert_eq!(parse("-o a --format ppm").unwrap().format, Format::Ppm);
+        assert_eq!(parse("-o a.unknown").unwrap().format, Format::Png);
+    }
+
+    #[test]
+    fn keyframes() {
+        let text =
+            "# center zoom rotation iterations offset\n\n-0.5,0.1 1\n  -0.75,0 1e3 90 500 0.25\n";
+        let keyframes = parse_keyframes(text).unwrap();
+
+        assert_eq!(keyframes.len(), 2);
+        assert_eq!(keyframes[0], Keyframe::new((-0.5, 0.1), 1.));
+        assert_eq!(
+            keyframes[1],
+            Keyframe::new((-0.75, 0.), 1000.)
+                .rotation(90_f64.to_radians())
+                .iterations(500)
+                .palette_offset(0.25)
+        );
+
+        assert!(parse_keyframes("# nothing\n").is_err());
+        assert!(parse_keyframes("-0.5,0.1").is_err());
+        assert!(parse_keyframes("-0.5,0.1 1 0 100 0 7").is_err());
+        assert!(parse_keyframes("-0.5 1").is_err());
+        assert!(parse_keyframes("-0.5,0.1 0").is_err());
+        assert!(parse_keyframes("-0.5,0.1 -2").is_err());
+        assert!(parse_keyframes("nan,0.1 1").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("--help").err().unwrap(), "");
+        assert_eq!(parse("").err().unwrap(), "Missing output file");
+        assert_eq!(parse("-o").err().unwrap(), "Missing value for '-o'");
+        assert_eq!(
+            parse("-o a.png --radius 2").err().unwrap(),
+            "Unknown argument '--radius'"
+        );
+        assert_eq!(
+            parse("-o a.png -s 0x10").err().unwrap(),
+            "Image size can't be zero"
+        );
+        assert!(parse("-o a.png -s 10").is_err());
+        assert!(parse("-o a.png -z x").is_err());
+        assert!(parse("-o a.png -p blue").is_err());
+    }
+
+    #[test]
+    fn views_have_to_be_valid() {
+        for args in [
+            "-z 0",
+            "-z -1",
+            "-z inf",
+            "-z NaN",
+            "-c inf,0",
+            "-c 0,NaN",
+            "--x-range 1,-1 --y-range -1,1",
+            "--x-range -1,1 --y-range -inf,1",
+            "--zoom-sequence 0",
+            "--exp-map -5",
+            "--palette-loop 0",
+        ] {
+            assert!(parse(&format!("-o a.png {args}")).is_err(), "{args}");
+        }
+
+        assert_eq!(
+            parse("-o a.png --frames 0").err().unwrap(),
+            "Frames can't be zero"
+        );
+        assert!(parse("-o a.png -z 1e-3 -c -1.5,0.5").is_ok());
+    }
+}
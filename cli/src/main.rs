@@ -0,0 +1,362 @@
+use core::animation::Animation;
+use core::exp_map::ExpMap;
+use core::hdr::{radiance, ToneMapper};
+use core::kf;
+use core::location::{self, Location};
+use core::palette_loop::PaletteLoop;
+use core::raw::{self, Channel};
+use core::tiles::{Layout, Pyramid};
+use core::y4m::Y4mWriter;
+use core::zoom_sequence::ZoomSequence;
+use core::{parallel_png, ppm, CancelToken, Dim, Mandelbrot, Strips};
+use pbr::ProgressBar;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use std::{env, process};
+
+mod args;
+
+use args::{Args, Format, USAGE};
+
+///Rows that are rendered and written at once
+const STRIP_HEIGHT: usize = 256;
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{message}\n");
+            }
+            eprintln!("{USAGE}");
+            process::exit(1);
+        }
+    };
+
+    let set = Mandelbrot::from_range(
+        Dim::new(args.size.0, args.size.1),
+        args.x_range,
+        args.y_range,
+    )
+    .max_iterations(args.iterations)
+    .radius(args.radius)
+    .rotation(args.rotation)
+    .fractal(args.fractal)
+    .palette(args.palette)
+    .palette_offset(args.palette_offset);
+
+    for warning in &args.warnings {
+        eprintln!("Warning: {warning}");
+    }
+
+    if let Some(path) = &args.save_location {
+        let location = location(&args, &set).tone_mapper(tone_mapper(&args));
+        let size = Dim::new(args.size.0, args.size.1);
+        let warnings = match kf::Kind::from_path(path) {
+            Some(kf::Kind::Location) => kf::save_location(path, &location, size),
+            Some(kf::Kind::Palette) => kf::save_palette(path, &location),
+            None => location::file::save(path, &location, Some(size)).map(|()| Vec::new()),
+        }
+        .expect("Failed to save the location");
+        for warning in warnings {
+            eprintln!("Warning: {warning}");
+        }
+        eprintln!("Location saved to     {}", path.display());
+
+        if args.output.as_os_str().is_empty() {
+            return;
+        }
+    }
+
+    eprintln!("Image size            {} x {}", args.size.0, args.size.1);
+
+    if args.keyframes.is_some() {
+        return render_animation(&args);
+    }
+    if let Some(end_zoom) = args.exp_map {
+        return render_exp_map(&args, set, end_zoom);
+    }
+    if let Some(end_zoom) = args.zoom_sequence {
+        return render_zoom_sequence(&args, set, end_zoom);
+    }
+    if let Some(channel) = args.data {
+        return export_data(&args, &set, channel);
+    }
+    if let Some(path) = &args.recolor {
+        return recolor(&args, &set, path);
+    }
+    if let Some(frames) = args.palette_loop {
+        return render_palette_loop(&args, set, frames);
+    }
+
+    match args.format {
+        Format::Png | Format::Ppm => render_image(&args, &set),
+        Format::Hdr => render_hdr(&args, &set),
+        Format::Dzi => export_pyramid(&args, set, Layout::DeepZoom),
+        Format::Xyz => export_pyramid(&args, set, Layout::Xyz),
+        Format::Gif | Format::Y4m => unreachable!("only accepted for animations"),
+    }
+}
+
+///Video stream of the animation frames, into the output file or stdout for `-`
+fn video(args: &Args) -> Y4mWriter<Box<dyn Write>> {
+    let writer: Box<dyn Write> = if args.output.as_os_str() == "-" {
+        Box::new(BufWriter::new(io::stdout().lock()))
+    } else {
+        Box::new(BufWriter::new(
+            File::create(&args.output).expect("Failed to create the video"),
+        ))
+    };
+
+    Y4mWriter::new(writer, args.size.0, args.size.1)
+        .fps(args.fps, 1)
+        .matrix(args.matrix)
+        .subsampling(args.subsampling)
+}
+
+fn render_image(args: &Args, set: &Mandelbrot) {
+    let (width, height) = (args.size.0, args.size.1);
+    let tone_mapper = tone_mapper(args);
+    let mut calculation = Duration::ZERO;
+    let mut drawing = Duration::ZERO;
+
+    let mut progress = ProgressBar::on(io::stderr(), height.div_ceil(STRIP_HEIGHT) as u64);
+    progress.message("Rendering ");
+
+    //Only one strip is in memory at a time, and the rows above the real axis that get mirrored
+    let mut strips = Strips::new(set);
+    let strip = |y, rows| {
+        let now = Instant::now();
+        let iterations = strips.next(y, rows);
+        calculation += now.elapsed();
+
+        let now = Instant::now();
+        //Without tone mapping and dithering the colors stay the same, there is no need to go through linear light
+        let colors = if tone_mapper == ToneMapper::default() {
+            iterations.map(|&iter| set.color(iter))
+        } else {
+            //Dithering needs the unquantized palette colors.
+            //Strips are made of whole rows of dithering blocks, except for the last one
+            let linear = iterations.map(|&iter| set.color_linear(iter));
+            tone_mapper.map_region(&linear, 0, y)
+        };
+        drawing += now.elapsed();
+
+        progress.inc();
+        colors
+    };
+
+    let location = location(args, set).tone_mapper(tone_mapper);
+    let now = Instant::now();
+    match args.format {
+        Format::Png => parallel_png::save_strips(
+            &args.output,
+            width,
+            height,
+            STRIP_HEIGHT,
+            Some(&location),
+            strip,
+        ),
+        _ => ppm::save_strips(&args.output, width, height, STRIP_HEIGHT, strip),
+    }
+    .expect("Failed to save image");
+    let writing = now.elapsed() - calculation - drawing;
+    progress.finish_println("");
+
+    eprintln!("Calculation took      {:.2?}", calculation);
+    eprintln!("Drawing took          {:.2?}", drawing);
+    eprintln!("Writing took          {:.2?}", writing);
+}
+
+///Location stored in png images, with the center and zoom exactly as given so it opens the same view
+fn location(args: &Args, set: &Mandelbrot) -> Location {
+    Location {
+        center: args.center,
+        zoom: args.zoom,
+        ..Location::of(set)
+    }
+}
+
+fn tone_mapper(args: &Args) -> ToneMapper {
+    ToneMapper::new(args.tone_map)
+        .exposure(args.exposure)
+        .dither(args.dither)
+}
+
+fn render_hdr(args: &Args, set: &Mandelbrot) {
+    let now = Instant::now();
+    let colors = set.linear_map();
+    eprintln!("Rendering took        {:.2?}", now.elapsed());
+
+    let now = Instant::now();
+    radiance::save(&args.output, &colors).expect("Failed to save image");
+    eprintln!("Writing took          {:.2?}", now.elapsed());
+}
+
+fn export_pyramid(args: &Args, set: Mandelbrot, layout: Layout) {
+    let pyramid = Pyramid::new(set)
+        .tile_size(args.tile_size)
+        .overlap(args.overlap)
+        .layout(layout)
+        .tone_mapper(tone_mapper(args));
+
+    let mut progress = ProgressBar::on(io::stderr(), pyramid.max_level() as u64 + 1);
+    progress.message("Levels ");
+
+    let now = Instant::now();
+    let tiles = pyramid
+        .export(&args.output, |_| {
+            progress.inc();
+        })
+        .expect("Failed to save tiles");
+    progress.finish_println("");
+
+    eprintln!("Tiles                 {}", tiles);
+    eprintln!("Rendering took        {:.2?}", now.elapsed());
+}
+
+fn render_animation(args: &Args) {
+    let keyframes = args.keyframes.clone().unwrap_or_default();
+    let animation = Animation::new(keyframes, args.frames, Dim::new(args.size.0, args.size.1))
+        .fractal(args.fractal)
+        .palette(args.palette)
+        .radius(args.radius);
+
+    let mut progress = ProgressBar::on(io::stderr(), args.frames as u64);
+    progress.message("Frames ");
+
+    let now = Instant::now();
+    let update = |done| {
+        progress.set(done as u64);
+    };
+    let rendered = match args.format {
+        Format::Y4m => animation.stream(&mut video(args), &CancelToken::new(), update),
+        _ => animation.render(&args.output, &CancelToken::new(), update),
+    }
+    .expect("Failed to save frames");
+    progress.finish_println("");
+
+    eprintln!(
+        "Frames                {} of {} rendered",
+        rendered, args.frames
+    );
+    eprintln!("Rendering took        {:.2?}", now.elapsed());
+}
+
+fn render_exp_map(args: &Args, set: Mandelbrot, end_zoom: f64) {
+    let (center, start_zoom) = (args.center, args.zoom);
+    let exp_map = ExpMap::new(set, center, start_zoom, end_zoom);
+
+    let size = exp_map.size();
+    eprintln!("Strip size            {} x {}", size.x, size.y);
+
+    let now = Instant::now();
+    let strip = exp_map.render();
+    eprintln!("Strip took            {:.2?}", now.elapsed());
+
+    let mut stream = None;
+    if args.format == Format::Y4m {
+        stream = Some(video(args));
+    } else {
+        fs::create_dir_all(&args.output).expect("Failed to create the output directory");
+        parallel_png::save(&args.output.join("strip.png"), &strip, None)
+            .expect("Failed to save strip");
+    }
+
+    let mut progress = ProgressBar::on(io::stderr(), args.frames as u64);
+    progress.message("Frames ");
+
+    let now = Instant::now();
+    for frame in 0..args.frames {
+        let zoom = exp_map.zoom(frame, args.frames);
+        let colors = exp_map.frame(&strip, zoom);
+        match &mut stream {
+            Some(stream) => stream.write_frame(&colors),
+            None => {
+                let location = Location {
+                    center,
+                    zoom,
+                    ..Location::of(&exp_map.set)
+                };
+                let path = Animation::frame_path(&args.output, frame);
+                parallel_png::save(&path, &colors, Some(&location))
+            }
+        }
+        .expect("Failed to save frame");
+        progress.inc();
+    }
+    if let Some(stream) = &mut stream {
+        stream.flush().expect("Failed to save frame");
+    }
+    progress.finish_println("");
+
+    eprintln!("Assembling took       {:.2?}", now.elapsed());
+}
+
+fn render_zoom_sequence(args: &Args, set: Mandelbrot, end_zoom: f64) {
+    let (center, start_zoom) = (args.center, args.zoom);
+    let sequence = ZoomSequence::new(set, center, start_zoom, end_zoom, args.frames);
+
+    let keyframes = sequence.keyframes();
+    eprintln!("Keyframes             {}", keyframes);
+
+    //Streamed keyframes are rendered in between the frames
+    let mut progress = match args.format {
+        Format::Y4m => ProgressBar::on(io::stderr(), args.frames as u64),
+        _ => ProgressBar::on(io::stderr(), (keyframes + args.frames) as u64),
+    };
+    progress.message("Frames ");
+
+    let now = Instant::now();
+    let update = |done| {
+        progress.set(done as u64);
+    };
+    let written = match args.format {
+        Format::Y4m => sequence.stream(&mut video(args), &CancelToken::new(), update),
+        _ => sequence.render(&args.output, &CancelToken::new(), update),
+    }
+    .expect("Failed to save frames");
+    progress.finish_println("");
+
+    eprintln!(
+        "Frames                {} of {} rendered",
+        written, args.frames
+    );
+    eprintln!("Rendering took        {:.2?}", now.elapsed());
+}
+
+fn render_palette_loop(args: &Args, set: Mandelbrot, frames: usize) {
+    let palette_loop = PaletteLoop::new(set, frames).fps(args.fps.min(u16::MAX as u32) as u16);
+
+    let now = Instant::now();
+    match args.format {
+        Format::Gif => palette_loop.save_gif(&args.output),
+        _ => palette_loop.save_apng(&args.output),
+    }
+    .expect("Failed to save the palette loop");
+
+    eprintln!("Frames                {}", frames);
+    eprintln!("Rendering took        {:.2?}", now.elapsed());
+}
+
+fn export_data(args: &Args, set: &Mandelbrot, channel: Channel) {
+    let now = Instant::now();
+    let samples = set.sample_map();
+    eprintln!("Calculation took      {:.2?}", now.elapsed());
+
+    raw::save(&args.output, &samples, channel).expect("Failed to save data");
+}
+
+fn recolor(args: &Args, set: &Mandelbrot, path: &Path) {
+    let values = raw::load(path).expect("Failed to load data");
+    eprintln!("Data size             {} x {}", values.width, values.height);
+
+    let colors = values.map(|&value| set.color_value(value));
+    match args.format {
+        Format::Ppm => ppm::save(&args.output, &colors),
+        _ => parallel_png::save(&args.output, &colors, Some(&location(args, set))),
+    }
+    .expect("Failed to save image");
+}
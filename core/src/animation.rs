@@ -0,0 +1,327 @@
+//!Zoom animations between keyframes, rendered into a numbered png sequence or a video stream.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::color::Palette;
+use crate::location::Location;
+use crate::y4m::Y4mWriter;
+use crate::{parallel_png, CancelToken, ColorMap, Dim, Fractal, Mandelbrot, Strategy};
+
+///Width of the real range at zoom 1
+pub const DEFAULT_WIDTH: f64 = 2.47;
+
+///View at one point of an animation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub center: (f64, f64),
+    ///Size of the default view divided by the size of this one
+    pub zoom: f64,
+    ///Counterclockwise rotation in radians
+    pub rotation: f64,
+    pub iterations: u64,
+    ///Shifts the colors along the palette, from 0 to 1
+    pub palette_offset: f32,
+}
+
+impl Keyframe {
+    pub fn new(center: (f64, f64), zoom: f64) -> Self {
+        Self {
+            center,
+            zoom,
+            rotation: 0.,
+            iterations: 1_000,
+            palette_offset: 0.,
+        }
+    }
+
+    pub fn rotation(mut self, angle: f64) -> Self {
+        self.rotation = angle;
+        self
+    }
+
+    pub fn iterations(mut self, n: u64) -> Self {
+        self.iterations = n;
+        self
+    }
+
+    pub fn palette_offset(mut self, offset: f32) -> Self {
+        self.palette_offset = offset;
+        self
+    }
+
+    ///Real and imaginary range of the view for an image size
+    pub fn ranges(&self, size: Dim<usize>) -> ((f64, f64), (f64, f64)) {
+        let width = DEFAULT_WIDTH / self.zoom;
+        let height = width * size.y as f64 / size.x as f64;
+
+        (
+            (self.center.0 - width / 2., self.center.0 + width / 2.),
+            (self.center.1 - height / 2., self.center.1 + height / 2.),
+        )
+    }
+}
+
+///Catmull-Rom spline through `p1` and `p2`, `t` goes from 0 to 1
+fn catmull_rom(p0: f64, p1: f64, p2: f64, p3: f64, t: f64) -> f64 {
+    let (t2, t3) = (t * t, t * t * t);
+
+    0.5 * (2. * p1
+        + (p2 - p0) * t
+        + (2. * p0 - 5. * p1 + 4. * p2 - p3) * t2
+        + (3. * p1 - p0 - 3. * p2 + p3) * t3)
+}
+
+///Keyframes spread evenly over a number of frames
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub keyframes: Vec<Keyframe>,
+    pub frames: usize,
+    pub size: Dim<usize>,
+    pub fractal: Fractal,
+    pub palette: Palette,
+    pub radius: f64,
+    pub strategy: Strategy,
+}
+
+impl Animation {
+    pub fn new(keyframes: Vec<Keyframe>, frames: usize, size: Dim<usize>) -> Self {
+        Self {
+            keyframes,
+            frames,
+            size,
+            fractal: Fractal::Mandelbrot,
+            palette: Palette::Rainbow,
+            radius: 2.,
+            strategy: Strategy::BruteForce,
+        }
+    }
+
+    pub fn fractal(mut self, fractal: Fractal) -> Self {
+        self.fractal = fractal;
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn radius(mut self, r: f64) -> Self {
+        self.radius = r;
+        self
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    ///Interpolated view of a frame.
+    ///
+    ///Zoom and iterations change exponentially, so the zoom speed stays the same.
+    ///The center follows a spline through all centers, parameterized by how far the view size
+    ///has shrunk, which keeps the next center steady on screen while zooming in on it.
+    pub fn keyframe(&self, frame: usize) -> Keyframe {
+        let keys = &self.keyframes;
+        if keys.len() < 2 || self.frames < 2 {
+            return keys
+                .first()
+                .copied()
+                .unwrap_or(Keyframe::new((-0.765, 0.), 1.));
+        }
+
+        //Position along the keyframes
+        let position =
+            frame.min(self.frames - 1) as f64 / (self.frames - 1) as f64 * (keys.len() - 1) as f64;
+        let i = (position.floor() as usize).min(keys.len() - 2);
+        let t = position - i as f64;
+
+        let (a, b) = (keys[i], keys[i + 1]);
+        let zoom = a.zoom * (b.zoom / a.zoom).powf(t);
+
+        //Share of the change in view size, from 0 to 1
+        let u = if (a.zoom - b.zoom).abs() > f64::EPSILON * a.zoom {
+            (1. / a.zoom - 1. / zoom) / (1. / a.zoom - 1. / b.zoom)
+        } else {
+            t
+        };
+
+        let before = keys[i.saturating_sub(1)].center;
+        let after = keys[(i + 2).min(keys.len() - 1)].center;
+        let center = (
+            catmull_rom(before.0, a.center.0, b.center.0, after.0, u),
+            catmull_rom(before.1, a.center.1, b.center.1, after.1, u),
+        );
+
+        let iterations = a.iterations.max(1) as f64
+            * (b.iterations.max(1) as f64 / a.iterations.max(1) as f64).powf(t);
+
+        Keyframe {
+            center,
+            zoom,
+            rotation: a.rotation + (b.rotation - a.rotation) * t,
+            iterations: iterations.round() as u64,
+            palette_offset: a.palette_offset + (b.palette_offset - a.palette_offset) * t as f32,
+        }
+    }
+
+    ///Render settings of a frame
+    pub fn frame_set(&self, frame: usize) -> Mandelbrot {
+        let key = self.keyframe(frame);
+        let (x_range, y_range) = key.ranges(self.size);
+
+        Mandelbrot::from_range(self.size, x_range, y_range)
+            .max_iterations(key.iterations)
+            .radius(self.radius)
+            .fractal(self.fractal)
+            .palette(self.palette)
+            .palette_offset(key.palette_offset)
+            .rotation(key.rotation)
+            .strategy(self.strategy)
+    }
+
+    pub fn render_frame(&self, frame: usize) -> ColorMap<[u8; 3]> {
+        let set = self.frame_set(frame);
+        set.iteration_map().map(|&iter| set.color(iter))
+    }
+
+    ///File of a frame inside the output directory
+    pub fn frame_path(dir: &Path, frame: usize) -> PathBuf {
+        dir.join(format!("frame_{frame:05}.png"))
+    }
+
+    ///Frames at the start of the sequence that already exist in the directory
+    pub fn completed_frames(&self, dir: &Path) -> usize {
+        (0..self.frames)
+            .find(|&frame| !Self::frame_path(dir, frame).exists())
+            .unwrap_or(self.frames)
+    }
+
+    ///Render every frame into the directory, starting after the last completed frame.
+    ///
+    ///Every frame is written to a temporary file first, so an interrupted render never leaves a broken frame.
+    ///`progress` gets the number of finished frames, returns how many frames were rendered.
+    pub fn render(
+        &self,
+        dir: &Path,
+        cancel: &CancelToken,
+        mut progress: impl FnMut(usize),
+    ) -> io::Result<usize> {
+        fs::create_dir_all(dir)?;
+
+        let start = self.completed_frames(dir);
+        progress(start);
+
+        for frame in start..self.frames {
+            if cancel.is_cancelled() {
+                return Ok(frame - start);
+            }
+
+            let path = Self::frame_path(dir, frame);
+            let temporary = path.with_extension("png.part");
+            let location = Location::of(&self.frame_set(frame));
+            parallel_png::save(&temporary, &self.render_frame(frame), Some(&location))?;
+            fs::rename(&temporary, &path)?;
+
+            progress(frame + 1);
+        }
+
+        Ok(self.frames - start)
+    }
+
+    ///Render every frame into a video stream, returns how many frames were written.
+    ///
+    ///The stream must have the size of the animation.
+    pub fn stream<W: Write>(
+        &self,
+        writer: &mut Y4mWriter<W>,
+        cancel: &CancelToken,
+        mut progress: impl FnMut(usize),
+    ) -> io::Result<usize> {
+        for frame in 0..self.frames {
+            if cancel.is_cancelled() {
+                return Ok(frame);
+            }
+
+            writer.write_frame(&self.render_frame(frame))?;
+            progress(frame + 1);
+        }
+
+        writer.flush()?;
+        Ok(self.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn animation(frames: usize) -> Animation {
+        let keyframes = vec![
+            Keyframe::new((-0.765, 0.), 1.).iterations(100),
+            Keyframe::new((-0.75, 0.1), 100.).iterations(400),
+            Keyframe::new((-0.7436, 0.1318), 10_000.).rotation(1.),
+        ];
+        Animation::new(keyframes, frames, Dim::new(32, 24))
+    }
+
+    #[test]
+    fn passes_through_the_keyframes() {
+        let animation = animation(5);
+        for (frame, key) in [(0, 0), (2, 1), (4, 2)] {
+            let expected = animation.keyframes[key];
+            let actual = animation.keyframe(frame);
+
+            assert!((actual.zoom / expected.zoom - 1.).abs() < 1e-12);
+            assert!((actual.center.0 - expected.center.0).abs() < 1e-12);
+            assert!((actual.center.1 - expected.center.1).abs() < 1e-12);
+            assert_eq!(actual.iterations, expected.iterations);
+        }
+    }
+
+    #[test]
+    fn zooms_exponentially() {
+        let animation = animation(9);
+        let (zoom, next) = (animation.keyframe(1).zoom, animation.keyframe(2).zoom);
+        assert!((zoom * zoom / next - 1.).abs() < 1e-9);
+        assert!((animation.keyframe(6).rotation - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn frames_use_fractal_and_palette() {
+        let animation = animation(5)
+            .fractal(Fractal::BurningShip)
+            .palette(Palette::Fire)
+            .radius(10.);
+        let set = animation.frame_set(3);
+
+        assert_eq!(set.fractal, Fractal::BurningShip);
+        assert_eq!(set.palette, Palette::Fire);
+        assert_eq!(set.radius, 10.);
+        assert_eq!(set.image_size, Dim::new(32, 24));
+    }
+
+    #[test]
+    fn resumes_after_the_completed_frames() {
+        let temp = TempDir::new("animation");
+        let dir = temp.join("frames");
+        let animation = animation(4);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert_eq!(animation.render(&dir, &cancel, |_| ()).unwrap(), 0);
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(Animation::frame_path(&dir, 0), "").unwrap();
+        assert_eq!(animation.completed_frames(&dir), 1);
+
+        let mut progress = Vec::new();
+        let rendered = animation.render(&dir, &CancelToken::new(), |done| progress.push(done));
+        assert_eq!(rendered.unwrap(), 3);
+        assert_eq!(progress, vec![1, 2, 3, 4]);
+        assert_eq!(animation.completed_frames(&dir), 4);
+    }
+}
@@ -0,0 +1,584 @@
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+use crate::{color, ColorMap, Mandelbrot};
+
+///Rows whose samples get calculated together when rendering a whole image
+const BAND_HEIGHT: usize = 16;
+
+///Sample in the support of a filter: the pixel that placed it, its index there
+///and its position relative to the center of the filtered pixel
+type SupportSample = ((isize, isize), usize, (f64, f64));
+
+///Where the samples of a pixel are placed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    ///Regular square grid
+    Grid,
+    ///Square grid rotated by atan(1/2), so no two samples share a row or column
+    RotatedGrid,
+    ///One random sample in every cell of the grid, the same for every render
+    Jittered,
+}
+
+///Reconstruction filter that weights the samples by their distance to the pixel center
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Box,
+    Tent,
+    ///Lanczos with 2 lobes, can slightly sharpen edges
+    Lanczos,
+}
+
+impl Filter {
+    ///Distance in pixels at which the weight drops to zero
+    pub fn radius(self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Lanczos => 2.,
+        }
+    }
+
+    ///Weight of a sample in one dimension, samples on the edge of the box still count
+    pub fn weight(self, d: f64) -> f64 {
+        let d = d.abs();
+        if d > self.radius() {
+            return 0.;
+        }
+
+        match self {
+            Filter::Box => 1.,
+            Filter::Tent => 1. - d,
+            Filter::Lanczos => sinc(d) * sinc(d / 2.),
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0. {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+///Deterministic random number from 0 to 1
+fn random(x: usize, y: usize, i: usize) -> f64 {
+    //splitmix64
+    let mut z = (x as u64) ^ ((y as u64) << 21) ^ ((i as u64) << 42);
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+
+    (z >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+///Supersampling antialiasing, every pixel is the filtered average of multiple samples
+#[derive(Clone, Copy, Debug)]
+pub struct Ssaa {
+    pub pattern: Pattern,
+    ///Samples every pixel places inside of its own area, a square number
+    pub samples: usize,
+    pub filter: Filter,
+}
+
+impl Ssaa {
+    ///Panics if `samples` isn't a square number, the patterns are built on a square grid
+    pub fn new(samples: usize) -> Self {
+        let side = (samples as f64).sqrt().round() as usize;
+        assert!(
+            side > 0 && side * side == samples,
+            "{samples} samples per pixel are no square number"
+        );
+
+        Self {
+            pattern: Pattern::Grid,
+            samples,
+            filter: Filter::Box,
+        }
+    }
+
+    pub fn pattern(mut self, pattern: Pattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    ///Samples along one side of the grid
+    fn side(&self) -> usize {
+        ((self.samples as f64).sqrt().round() as usize).max(1)
+    }
+
+    ///Neighboring pixels on every side whose samples the filter reaches
+    fn reach(&self) -> usize {
+        (self.filter.radius() - 0.5).ceil() as usize
+    }
+
+    ///Positions of the samples a pixel places inside of its own area, relative to its center
+    fn cell_offsets(&self, x: isize, y: isize) -> Vec<(f64, f64)> {
+        let side = self.side();
+        let angle = 0.5_f64.atan();
+
+        (0..side * side)
+            .map(|i| {
+                let (column, row) = ((i % side) as f64, (i / side) as f64);
+
+                //From 0 to 1
+                let (u, v) = match self.pattern {
+                    Pattern::Grid => ((column + 0.5) / side as f64, (row + 0.5) / side as f64),
+                    Pattern::RotatedGrid => {
+                        let u = (column + 0.5) / side as f64 - 0.5;
+                        let v = (row + 0.5) / side as f64 - 0.5;
+
+                        //Rotate and wrap around, which keeps the samples evenly spread
+                        let ru = u * angle.cos() - v * angle.sin();
+                        let rv = u * angle.sin() + v * angle.cos();
+                        ((ru + 0.5).rem_euclid(1.), (rv + 0.5).rem_euclid(1.))
+                    }
+                    Pattern::Jittered => (
+                        (column + random(x as usize, y as usize, 2 * i)) / side as f64,
+                        (row + random(x as usize, y as usize, 2 * i + 1)) / side as f64,
+                    ),
+                };
+
+                (u - 0.5, v - 0.5)
+            })
+            .collect()
+    }
+
+    ///Samples the filter of a pixel covers
+    fn support(&self, x: isize, y: isize) -> Vec<SupportSample> {
+        let reach = self.reach() as isize;
+        let mut support = Vec::new();
+
+        for cy in -reach..=reach {
+            for cx in -reach..=reach {
+                let cell = (x + cx, y + cy);
+                for (i, (dx, dy)) in self.cell_offsets(cell.0, cell.1).into_iter().enumerate() {
+                    let (dx, dy) = (dx + cx as f64, dy + cy as f64);
+                    if self.filter.weight(dx) * self.filter.weight(dy) != 0. {
+                        support.push((cell, i, (dx, dy)));
+                    }
+                }
+            }
+        }
+
+        support
+    }
+
+    ///Positions of the samples the filter of a pixel covers, relative to its center.
+    ///
+    ///Every pixel places its samples inside of its own area, filters wider than a box
+    ///also weight the samples of the pixels around it.
+    pub fn offsets(&self, x: usize, y: usize) -> Vec<(f64, f64)> {
+        self.support(x as isize, y as isize)
+            .into_iter()
+            .map(|(_, _, offset)| offset)
+            .collect()
+    }
+
+    ///Filtered linear color of a pixel, `color` gets the pixel that placed a sample,
+    ///the index of the sample there and its position
+    fn filtered(
+        &self,
+        x: isize,
+        y: isize,
+        color: impl Fn((isize, isize), usize, (f64, f64)) -> [f32; 3],
+    ) -> [f32; 3] {
+        let mut samples: Vec<([f32; 3], f64)> = self
+            .support(x, y)
+            .into_iter()
+            .map(|(cell, i, (dx, dy))| {
+                let linear = color(cell, i, (x as f64 + dx, y as f64 + dy));
+                (linear, self.filter.weight(dx) * self.filter.weight(dy))
+            })
+            .collect();
+
+        //Without any weight left every sample counts the same, like with the box filter
+        let mut total_weight: f64 = samples.iter().map(|(_, weight)| weight).sum();
+        if total_weight <= 0. {
+            samples.iter_mut().for_each(|(_, weight)| *weight = 1.);
+            total_weight = samples.len() as f64;
+        }
+
+        let mut sum = [0.; 3];
+        for (linear, weight) in samples {
+            for c in 0..3 {
+                sum[c] += linear[c] as f64 * weight;
+            }
+        }
+
+        sum.map(|c| (c / total_weight) as f32)
+    }
+
+    ///Antialiased color of a single pixel, averaged in linear light
+    pub fn pixel(&self, set: &Mandelbrot, x: usize, y: usize) -> [u8; 3] {
+        //Negative lobes can push the result out of range
+        color::from_linear(self.pixel_linear(set, x, y).map(|c| c.clamp(0., 1.)))
+    }
+
+    ///Antialiased linear color of a single pixel, not limited to the range from 0 to 1
+    pub fn pixel_linear(&self, set: &Mandelbrot, x: usize, y: usize) -> [f32; 3] {
+        self.filtered(x as isize, y as isize, |_, _, (px, py)| {
+            set.color_linear(set.get_pixel(px, py))
+        })
+    }
+
+    ///Render every pixel of the set with antialiasing into linear colors.
+    ///
+    ///The samples of every pixel only get calculated once, band by band of rows,
+    ///and are shared with the filters of the pixels around it.
+    pub fn render_linear(&self, set: &Mandelbrot) -> ColorMap<[f32; 3]> {
+        let (width, height) = (set.image_size.x, set.image_size.y);
+        let reach = self.reach();
+        let mut map = ColorMap::new(width, height);
+
+        for band in (0..height).step_by(BAND_HEIGHT) {
+            let rows = BAND_HEIGHT.min(height - band);
+
+            //Linear colors of the samples of the band and the pixels around it
+            let cells_width = width + 2 * reach;
+            let cells: Vec<Vec<[f32; 3]>> = (0..(rows + 2 * reach) * cells_width)
+                .into_par_iter()
+                .map(|i| {
+                    let x = (i % cells_width) as isize - reach as isize;
+                    let y = (band + i / cells_width) as isize - reach as isize;
+
+                    self.cell_offsets(x, y)
+                        .into_iter()
+                        .map(|(dx, dy)| {
+                            set.color_linear(set.get_pixel(x as f64 + dx, y as f64 + dy))
+                        })
+                        .collect()
+                })
+                .collect();
+
+            map.data[band * width..(band + rows) * width]
+                .par_chunks_mut(width.max(1))
+                .enumerate()
+                .for_each(|(row, colors)| {
+                    let y = (band + row) as isize;
+                    for (x, color) in colors.iter_mut().enumerate() {
+                        *color = self.filtered(x as isize, y, |(cx, cy), i, _| {
+                            let cx = (cx + reach as isize) as usize;
+                            let cy = (cy - band as isize + reach as isize) as usize;
+                            cells[cy * cells_width + cx][i]
+                        });
+                    }
+                });
+        }
+
+        map
+    }
+
+    ///Render every pixel of the set with antialiasing
+    pub fn render(&self, set: &Mandelbrot) -> ColorMap<[u8; 3]> {
+        self.render_linear(set)
+            .map(|&linear| color::from_linear(linear.map(|c| c.clamp(0., 1.))))
+    }
+}
+
+///Antialiasing that only supersamples pixels on edges, where neighbors differ strongly
+#[derive(Clone, Copy, Debug)]
+pub struct Adaptive {
+    ///Relative difference in iterations or linear color from 0 to 1 that marks an edge
+    pub threshold: f32,
+    ///Supersampling of the edge pixels, its sample count is the maximum per pixel
+    pub ssaa: Ssaa,
+}
+
+impl Adaptive {
+    pub fn new(threshold: f32, max_samples: usize) -> Self {
+        Self {
+            threshold,
+            ssaa: Ssaa::new(max_samples).pattern(Pattern::RotatedGrid),
+        }
+    }
+
+    pub fn pattern(mut self, pattern: Pattern) -> Self {
+        self.ssaa.pattern = pattern;
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.ssaa.filter = filter;
+        self
+    }
+
+    ///Whether two neighboring pixels are too different
+    fn differs(&self, a: (u64, [f32; 3]), b: (u64, [f32; 3])) -> bool {
+        let iterations = a.0.abs_diff(b.0) as f32 / a.0.max(b.0).max(1) as f32;
+
+        let (a, b) = (a.1, b.1);
+        let color = (0..3).map(|c| (a[c] - b[c]).abs()).fold(0., f32::max);
+
+        iterations > self.threshold || color > self.threshold
+    }
+
+    fn is_edge(
+        &self,
+        iterations: &ColorMap<u64>,
+        colors: &ColorMap<[f32; 3]>,
+        x: usize,
+        y: usize,
+    ) -> bool {
+        let pixel = (iterations[(x, y)], colors[(x, y)]);
+        let neighbors = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+
+        neighbors
+            .into_iter()
+            .filter(|&(nx, ny)| nx < iterations.width && ny < iterations.height)
+            .any(|n| self.differs(pixel, (iterations[n], colors[n])))
+    }
+
+    ///Pixels of a rendered image that need supersampling
+    fn edges(&self, iterations: &ColorMap<u64>, colors: &ColorMap<[f32; 3]>) -> Vec<usize> {
+        let width = iterations.width;
+
+        (0..iterations.data.len())
+            .into_par_iter()
+            .filter(|&i| self.is_edge(iterations, colors, i % width, i / width))
+            .collect()
+    }
+
+    ///Supersample the edges of a rendered image, returns how many pixels got refined.
+    ///
+    ///The new colors are averaged in linear light and only quantized once.
+    pub fn refine(
+        &self,
+        set: &Mandelbrot,
+        iterations: &ColorMap<u64>,
+        colors: &mut ColorMap<[u8; 3]>,
+    ) -> usize {
+        let width = iterations.width;
+        let edges = self.edges(iterations, &colors.map(|&color| color::to_linear(color)));
+
+        let refined: Vec<[u8; 3]> = edges
+            .par_iter()
+            .map(|&i| self.ssaa.pixel(set, i % width, i / width))
+            .collect();
+
+        for (&i, color) in edges.iter().zip(refined) {
+            colors.data[i] = color;
+        }
+
+        edges.len()
+    }
+
+    ///Supersample the edges of a rendered image in linear light, returns how many pixels got refined
+    pub fn refine_linear(
+        &self,
+        set: &Mandelbrot,
+        iterations: &ColorMap<u64>,
+        colors: &mut ColorMap<[f32; 3]>,
+    ) -> usize {
+        let width = iterations.width;
+        let edges = self.edges(iterations, colors);
+
+        let refined: Vec<[f32; 3]> = edges
+            .par_iter()
+            .map(|&i| self.ssaa.pixel_linear(set, i % width, i / width))
+            .collect();
+
+        for (&i, color) in edges.iter().zip(refined) {
+            colors.data[i] = color;
+        }
+
+        edges.len()
+    }
+
+    ///Render the set and supersample its edges in linear light, also returns how many pixels got refined
+    pub fn render_linear(&self, set: &Mandelbrot) -> (ColorMap<[f32; 3]>, usize) {
+        let iterations = set.iteration_map();
+        let mut colors = iterations.map(|&iter| set.color_linear(iter));
+
+        let refined = self.refine_linear(set, &iterations, &mut colors);
+        (colors, refined)
+    }
+
+    ///Render the set and supersample its edges, also returns how many pixels got refined
+    pub fn render(&self, set: &Mandelbrot) -> (ColorMap<[u8; 3]>, usize) {
+        let (linear, refined) = self.render_linear(set);
+        let colors = linear.map(|&linear| color::from_linear(linear.map(|c| c.clamp(0., 1.))));
+        (colors, refined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dim;
+
+    const PATTERNS: [Pattern; 3] = [Pattern::Grid, Pattern::RotatedGrid, Pattern::Jittered];
+    const FILTERS: [Filter; 3] = [Filter::Box, Filter::Tent, Filter::Lanczos];
+
+    fn set() -> Mandelbrot {
+        Mandelbrot::from_range(Dim::new(32, 24), (-0.8, -0.7), (0.05, 0.125)).max_iterations(300)
+    }
+
+    #[test]
+    fn only_edges_get_refined() {
+        let set = Mandelbrot::from_range(Dim::new(5, 5), (10., 11.), (10., 11.));
+        let mut iterations = ColorMap::new(5, 5);
+        iterations.data.fill(1);
+        iterations[(2, 2)] = 100;
+        let mut colors = iterations.map(|&iter| set.color(iter));
+        let before = colors.data.clone();
+
+        let adaptive = Adaptive::new(0.1, 4);
+        assert_eq!(adaptive.refine(&set, &iterations, &mut colors), 5);
+
+        let edges = [(2, 2), (1, 2), (3, 2), (2, 1), (2, 3)];
+        for y in 0..5 {
+            for x in 0..5 {
+                if edges.contains(&(x, y)) {
+                    assert_eq!(colors[(x, y)], adaptive.ssaa.pixel(&set, x, y));
+                } else {
+                    assert_eq!(colors[(x, y)], before[y * 5 + x]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_edges_stay_linear() {
+        let adaptive = Adaptive::new(0.1, 4);
+        let (linear, refined) = adaptive.render_linear(&set());
+        let (colors, quantized) = adaptive.render(&set());
+        assert!(refined > 0);
+        assert_eq!(refined, quantized);
+
+        let expected = linear.map(|&c| color::from_linear(c.map(|c| c.clamp(0., 1.))));
+        assert_eq!(colors.data, expected.data);
+
+        //Refined pixels are the linear average, not the one of their quantized colors
+        let set = set();
+        let iterations = set.iteration_map();
+        let edge = (0..linear.data.len())
+            .find(|&i| linear.data[i] != set.color_linear(iterations.data[i]))
+            .unwrap();
+        let (x, y) = (edge % linear.width, edge / linear.width);
+        assert_eq!(linear[(x, y)], adaptive.ssaa.pixel_linear(&set, x, y));
+    }
+
+    #[test]
+    fn samples_cover_the_filter() {
+        for pattern in PATTERNS {
+            for filter in FILTERS {
+                let offsets = Ssaa::new(9).pattern(pattern).filter(filter).offsets(5, 7);
+                let radius = filter.radius();
+                assert!(offsets
+                    .iter()
+                    .all(|&(dx, dy)| dx.abs() <= radius && dy.abs() <= radius));
+
+                //Wider filters reach into the neighboring pixels
+                assert_eq!(offsets.len() > 9, filter != Filter::Box, "{filter:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors_share_their_samples() {
+        let own = Ssaa::new(9).pattern(Pattern::Jittered).offsets(6, 7);
+        let tent = Ssaa::new(9)
+            .pattern(Pattern::Jittered)
+            .filter(Filter::Tent)
+            .offsets(5, 7);
+
+        let reached: Vec<_> = own
+            .iter()
+            .map(|&(dx, dy)| (dx + 1., dy))
+            .filter(|&(dx, dy)| dx < 1. && dy.abs() < 1.)
+            .collect();
+        assert!(!reached.is_empty());
+        assert!(reached.iter().all(|offset| tent.contains(offset)));
+    }
+
+    #[test]
+    fn lanczos_uses_its_negative_lobes() {
+        let ssaa = Ssaa::new(16).filter(Filter::Lanczos);
+        assert!(ssaa
+            .offsets(0, 0)
+            .iter()
+            .any(|&(dx, dy)| Filter::Lanczos.weight(dx) * Filter::Lanczos.weight(dy) < 0.));
+    }
+
+    #[test]
+    fn shared_samples_match_single_pixels() {
+        let set = set();
+        for filter in FILTERS {
+            let ssaa = Ssaa::new(4).pattern(Pattern::Jittered).filter(filter);
+            let colors = ssaa.render_linear(&set);
+
+            for (x, y) in [(0, 0), (31, 23), (12, 16), (20, 3)] {
+                assert_eq!(colors[(x, y)], ssaa.pixel_linear(&set, x, y), "{filter:?}");
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "no square number")]
+    fn sample_counts_have_to_be_square() {
+        Ssaa::new(8);
+    }
+
+    #[test]
+    fn small_differences_are_no_edge() {
+        let set = set();
+        let (colors, refined) = Adaptive::new(f32::INFINITY, 16).render(&set);
+        assert_eq!(refined, 0);
+        assert_eq!(
+            colors.data,
+            set.iteration_map().map(|&iter| set.color(iter)).data
+        );
+
+        let (_, refined) = Adaptive::new(0.05, 16).render(&set);
+        assert!(refined > 0 && refined < 32 * 24, "{refined}");
+    }
+
+    #[test]
+    fn every_sample_has_weight() {
+        for filter in FILTERS {
+            for (dx, dy) in Ssaa::new(16).filter(filter).offsets(0, 0) {
+                assert!(filter.weight(dx) * filter.weight(dy) != 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn lanczos_with_four_samples_is_finite() {
+        let colors = Ssaa::new(4).filter(Filter::Lanczos).render_linear(&set());
+        assert!(colors.data.iter().flatten().all(|c| c.is_finite()));
+    }
+
+    #[test]
+    fn uniform_pixels_keep_their_color() {
+        //Every sample escapes right away
+        let set = Mandelbrot::from_range(Dim::new(4, 4), (10., 11.), (10., 11.));
+        let expected = set.color(set.get_pixel(0., 0.));
+
+        for filter in FILTERS {
+            let colors = Ssaa::new(9).filter(filter).render(&set);
+            assert!(colors.data.iter().all(|&c| c == expected));
+        }
+    }
+
+    #[test]
+    fn one_sample_is_the_pixel_center() {
+        let set = set();
+        let colors = Ssaa::new(1).render(&set);
+        let expected = set.iteration_map().map(|&iter| set.color(iter));
+        assert_eq!(colors.data, expected.data);
+    }
+}
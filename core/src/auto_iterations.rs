@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ColorMap;
+
+///How the pixels of a finished frame escaped
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EscapeStats {
+    pub pixels: usize,
+    ///Pixels that reached max iterations
+    pub at_limit: usize,
+    ///Pixels that escaped during the second half of the iterations
+    pub late: usize,
+}
+
+impl EscapeStats {
+    pub fn new(iterations: &ColorMap<u64>, max_iterations: u64) -> Self {
+        let mut stats = Self {
+            pixels: iterations.data.len(),
+            ..Self::default()
+        };
+
+        for &iter in &iterations.data {
+            if iter >= max_iterations {
+                stats.at_limit += 1;
+            } else if iter > max_iterations / 2 {
+                stats.late += 1;
+            }
+        }
+
+        stats
+    }
+
+    ///Share of pixels that reached max iterations, from 0 to 1
+    pub fn limit_share(&self) -> f32 {
+        self.at_limit as f32 / self.pixels.max(1) as f32
+    }
+
+    ///Share of the pixels at the limit that are likely unresolved, from 0 to 1.
+    ///
+    ///This is the share of the pixels that reached half the iterations which escaped in the second half,
+    ///so how much doubling the iterations reduced the pixels at the limit. Pixels inside the set stay
+    ///at the limit however high it gets, so views with a lot of interior settle once doubling barely helps.
+    pub fn unresolved(&self) -> f32 {
+        if self.at_limit == 0 {
+            return 0.;
+        }
+
+        self.late as f32 / (self.late + self.at_limit) as f32
+    }
+}
+
+///Picks max iterations from the zoom level and the statistics of the previous frame
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoIterations {
+    ///Accepted share of unresolved pixels at the limit, from 0 to 1
+    pub target: f32,
+    ///Iterations at zoom level 1
+    pub base: u64,
+    ///Upper bound, the limit is never raised above it
+    pub max: u64,
+    ///How often one view gets rendered again with raised iterations
+    pub max_rerenders: u32,
+}
+
+impl Default for AutoIterations {
+    fn default() -> Self {
+        Self {
+            target: 0.01,
+            base: 100,
+            max: 1_000_000,
+            max_rerenders: 3,
+        }
+    }
+}
+
+impl AutoIterations {
+    ///Lowest iterations for a zoom level, where zoom is the size of the default view divided by the current one
+    pub fn minimum(&self, zoom: f64) -> u64 {
+        let depth = zoom.log10().max(0.);
+        (self.base as f64 * (1. + depth).powf(1.5)) as u64
+    }
+
+    ///Iterations for the next frame.
+    ///
+    ///Doubles the previous iterations while too many pixels are unresolved and lowers them
+    ///slowly once almost all are resolved, but never below the minimum for the zoom level.
+    pub fn next(&self, zoom: f64, previous: u64, stats: &EscapeStats) -> u64 {
+        let unresolved = stats.unresolved();
+
+        let next = if unresolved > self.target {
+            previous.saturating_mul(2)
+        } else if unresolved < self.target / 4. {
+            previous / 4 * 3
+        } else {
+            previous
+        };
+
+        next.clamp(self.minimum(zoom).min(self.max), self.max)
+    }
+
+    ///Raised iterations to render a finished view again with.
+    ///
+    ///`None` if the view needs no more iterations or was already rendered again `rerenders` times.
+    pub fn rerender(
+        &self,
+        zoom: f64,
+        previous: u64,
+        stats: &EscapeStats,
+        rerenders: u32,
+    ) -> Option<u64> {
+        let next = self.next(zoom, previous, stats);
+        (next > previous && rerenders < self.max_rerenders).then_some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(iterations: &[u64], max_iterations: u64) -> EscapeStats {
+        let map = ColorMap {
+            width: iterations.len(),
+            height: 1,
+            data: iterations.to_vec(),
+        };
+        EscapeStats::new(&map, max_iterations)
+    }
+
+    #[test]
+    fn counts_late_and_limit_pixels() {
+        let stats = stats(&[1, 50, 51, 99, 100, 100, 150, 3], 100);
+        assert_eq!((stats.pixels, stats.at_limit, stats.late), (8, 3, 2));
+        assert_eq!(stats.limit_share(), 3. / 8.);
+        assert_eq!(stats.unresolved(), 2. / 5.);
+    }
+
+    #[test]
+    fn nothing_is_unresolved_without_pixels_at_the_limit() {
+        assert_eq!(stats(&[60, 70, 80], 100).unresolved(), 0.);
+        assert_eq!(stats(&[], 100).limit_share(), 0.);
+    }
+
+    #[test]
+    fn minimum_grows_with_the_zoom() {
+        let auto = AutoIterations::default();
+        assert_eq!(auto.minimum(0.5), 100);
+        assert_eq!(auto.minimum(1.), 100);
+        assert_eq!(auto.minimum(1000.), 800);
+        assert!(auto.minimum(1e10) > auto.minimum(1e5));
+    }
+
+    #[test]
+    fn adapts_to_the_unresolved_pixels() {
+        let auto = AutoIterations::default();
+        let unresolved = stats(&[100, 90, 10, 10], 100);
+        let resolved = stats(&[100, 10, 10, 10], 100);
+
+        assert_eq!(auto.next(1., 1000, &unresolved), 2000);
+        assert_eq!(auto.next(1., 1000, &resolved), 750);
+        assert_eq!(auto.next(1., 100, &resolved), 100);
+        assert_eq!(auto.next(1., 800_000, &unresolved), auto.max);
+    }
+
+    #[test]
+    fn interior_pixels_are_no_reason_to_raise() {
+        //Half of the view is inside the set and only a few pixels escaped late
+        let mut iterations = vec![1000; 500];
+        iterations.extend([600, 700, 900]);
+        iterations.extend(vec![20; 497]);
+
+        let auto = AutoIterations::default();
+        assert_eq!(auto.next(1., 1000, &stats(&iterations, 1000)), 1000);
+    }
+
+    #[test]
+    fn rerenders_are_limited() {
+        let auto = AutoIterations::default();
+        let unresolved = stats(&[100, 90, 10, 10], 100);
+        let resolved = stats(&[100, 10, 10, 10], 100);
+
+        assert_eq!(auto.rerender(1., 1000, &unresolved, 0), Some(2000));
+        assert_eq!(auto.rerender(1., 1000, &unresolved, 3), None);
+        assert_eq!(auto.rerender(1., 1000, &resolved, 0), None);
+    }
+}
@@ -1,4 +1,5 @@
-use palette::{rgb::Rgb, Hsv, IntoColor, Pixel};
+use palette::{rgb::Rgb, Hsv, IntoColor, LinSrgb, Pixel, Srgb};
+use serde::{Deserialize, Serialize};
 
 #[allow(dead_code)]
 pub mod scale {
@@ -14,7 +15,65 @@ pub mod scale {
 
     ///Exponential scale, still needs some tuning
     pub fn exponential(iteration: u64) -> f32 {
-        -1. / (iteration as f32).powf(0.27) + 1.
+        exponential_value(iteration as f32)
+    }
+
+    ///Exponential scale of a continuous iteration count
+    pub fn exponential_value(value: f32) -> f32 {
+        -1. / value.powf(0.27) + 1.
+    }
+}
+
+///Colors for the scaled iteration count
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Palette {
+    ///Hue from red to purple, the original colors
+    Rainbow,
+    ///Black over red and yellow to white
+    Fire,
+    Grayscale,
+}
+
+impl Palette {
+    pub const ALL: [Palette; 3] = [Palette::Rainbow, Palette::Fire, Palette::Grayscale];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Palette::Rainbow => "rainbow",
+            Palette::Fire => "fire",
+            Palette::Grayscale => "grayscale",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    ///Color at a position from 0 to 1 in linear light, without any quantization
+    pub fn color_linear(self, t: f32) -> [f32; 3] {
+        let t = t.clamp(0., 1.);
+
+        let srgb = match self {
+            Palette::Rainbow => {
+                let hsv_c = Hsv::new(t * 300. + 20., 1., 1.);
+                let rgb_c: Rgb = hsv_c.into_color();
+                rgb_c.into_raw()
+            }
+            Palette::Fire => {
+                //Every channel fades in after the previous one
+                let channel = |start: f32| (t * 3. - start).clamp(0., 1.);
+                [channel(0.), channel(1.), channel(2.)]
+            }
+            Palette::Grayscale => [t, t, t],
+        };
+        let srgb: Srgb<f32> = *Srgb::from_raw(&srgb);
+        srgb.into_linear().into_raw()
+    }
+
+    ///Color at a position from 0 to 1 as 8 bit sRGB
+    pub fn color(self, t: f32) -> [u8; 3] {
+        from_linear(self.color_linear(t))
     }
 }
 
@@ -29,3 +88,56 @@ pub fn from_iterations(iteration: u64, used_scale: fn(u64) -> f32) -> [u8; 3] {
     //println!("{hsv_c:?}");
     rgb_c.into_format().into_raw()
 }
+
+///Convert an sRGB color to linear light, where colors can be averaged
+pub fn to_linear(color: [u8; 3]) -> [f32; 3] {
+    let srgb: Srgb<f32> = Srgb::from_raw(&color).into_format();
+    srgb.into_linear().into_raw()
+}
+
+///Convert a linear light color back to sRGB
+pub fn from_linear(color: [f32; 3]) -> [u8; 3] {
+    let linear: LinSrgb<f32> = *LinSrgb::from_raw(&color);
+    Srgb::from_linear(linear).into_format().into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_round_trip() {
+        for v in 0..=255 {
+            assert_eq!(from_linear(to_linear([v, v, v])), [v, v, v]);
+        }
+    }
+
+    #[test]
+    fn palettes_stay_in_range() {
+        for palette in Palette::ALL {
+            for i in 0..=100 {
+                let color = palette.color_linear(i as f32 / 100.);
+                assert!(color.iter().all(|c| (0. ..=1.).contains(c)), "{palette:?}");
+                assert_eq!(palette.color(i as f32 / 100.), from_linear(color));
+            }
+        }
+        assert_eq!(Palette::Grayscale.color(0.), [0, 0, 0]);
+        assert_eq!(Palette::Fire.color(1.), [255, 255, 255]);
+    }
+
+    #[test]
+    fn linear_colors_are_not_quantized() {
+        //Both positions round to the same 8 bit color
+        let (a, b) = (0.5, 0.5 + 0.5 / 255.);
+        let palette = Palette::Grayscale;
+        assert_eq!(palette.color(a), palette.color(b));
+        assert!(palette.color_linear(a)[0] < palette.color_linear(b)[0]);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for palette in Palette::ALL {
+            assert_eq!(Palette::from_name(palette.name()), Some(palette));
+        }
+    }
+}
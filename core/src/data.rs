@@ -1,21 +1,21 @@
 // For reading and opening files
-use std::io::BufWriter;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::{Index, IndexMut};
 use std::path::Path;
-use std::{fs::File, ops::Index};
 
-use crate::{Color, IMAGE_SIZE};
-
-const DATA_SIZE: usize = IMAGE_SIZE.0 * IMAGE_SIZE.1 * 4;
+use crate::{Mandelbrot, Strips};
 
+#[derive(Clone)]
 pub struct ColorMap<T> {
     pub width: usize,
     pub height: usize,
     // Data is stored in rows
     pub data: Vec<T>,
 }
-impl ColorMap {
+impl<T: Clone + Default> ColorMap<T> {
     pub fn new(width: usize, height: usize) -> Self {
-        let vec = Vec::with_capacity(width * height);
+        let data = vec![T::default(); width * height];
 
         Self {
             width,
@@ -24,11 +24,86 @@ impl ColorMap {
         }
     }
 }
-impl Index<(usize, usize)> for ColorMap<T> {
+impl<T: Copy + Default> ColorMap<T> {
+    ///Move the content by whole pixels, uncovered pixels get the default value
+    pub fn shift(&mut self, dx: isize, dy: isize) {
+        let mut data = vec![T::default(); self.data.len()];
+
+        for y in 0..self.height {
+            let source_y = y as isize - dy;
+            if source_y < 0 || source_y >= self.height as isize {
+                continue;
+            }
+
+            for x in 0..self.width {
+                let source_x = x as isize - dx;
+                if source_x < 0 || source_x >= self.width as isize {
+                    continue;
+                }
+
+                data[y * self.width + x] = self[(source_x as usize, source_y as usize)];
+            }
+        }
+
+        self.data = data;
+    }
+
+    ///Copy another map into this one, with its top left corner at the given position
+    pub fn paste(&mut self, x: usize, y: usize, other: &ColorMap<T>) {
+        for (row_y, row) in other.data.chunks(other.width.max(1)).enumerate() {
+            let start = (y + row_y) * self.width + x;
+            self.data[start..start + row.len()].copy_from_slice(row);
+        }
+    }
+
+    ///Copy of a rectangle of this map
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Self {
+        let mut data = Vec::with_capacity(width * height);
+        for row in y..y + height {
+            let start = row * self.width + x;
+            data.extend_from_slice(&self.data[start..start + width]);
+        }
+
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+}
+impl<T> ColorMap<T> {
+    ///Convert every value, keeping the dimensions
+    pub fn map<U>(&self, f: impl Fn(&T) -> U) -> ColorMap<U> {
+        ColorMap {
+            width: self.width,
+            height: self.height,
+            data: self.data.iter().map(f).collect(),
+        }
+    }
+}
+impl ColorMap<[u8; 3]> {
+    ///RGBA bytes with full opacity, ready for an image buffer
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(self.data.len() * 4);
+
+        for color in &self.data {
+            data.extend_from_slice(color);
+            data.push(255);
+        }
+
+        data
+    }
+}
+impl<T> Index<(usize, usize)> for ColorMap<T> {
     type Output = T;
     fn index(&self, index: (usize, usize)) -> &Self::Output {
         // y * width is the offset of rows
-        self.data[index.1 * self.width + index.0]
+        &self.data[index.1 * self.width + index.0]
+    }
+}
+impl<T> IndexMut<(usize, usize)> for ColorMap<T> {
+    fn index_mut(&mut self, index: (usize, usize)) -> &mut Self::Output {
+        &mut self.data[index.1 * self.width + index.0]
     }
 }
 
@@ -116,12 +191,12 @@ pub mod png_pong_crate {
 #[allow(dead_code)]
 pub mod png_crate {
     use super::*;
-    pub fn save_file(data: &[u8]) {
+    pub fn save_file(data: &[u8], width: usize, height: usize) {
         let path = Path::new(r"./png_crate.png");
         let file = File::create(path).unwrap();
-        let ref mut w = BufWriter::new(file);
+        let w = &mut BufWriter::new(file);
 
-        let mut encoder = png::Encoder::new(w, IMAGE_SIZE.0 as u32, IMAGE_SIZE.1 as u32); // Width is 2 pixels and height is 1.
+        let mut encoder = png::Encoder::new(w, width as u32, height as u32);
         encoder.set_color(png::ColorType::Rgba);
         encoder.set_depth(png::BitDepth::Eight);
 
@@ -132,21 +207,199 @@ pub mod png_crate {
         writer.write_image_data(data).unwrap(); // Save
     }
 
+    ///Save a color map as RGB png
+    pub fn save(path: &Path, map: &ColorMap<[u8; 3]>) -> io::Result<()> {
+        let file = File::create(path)?;
+        write(BufWriter::new(file), map)
+    }
+
+    ///Encode a color map as RGB png into any writer, like a buffer in memory
+    pub fn write<W: Write>(w: W, map: &ColorMap<[u8; 3]>) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(w, map.width as u32, map.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(map.data.concat().as_slice())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    ///Load a png with 8 or 16 bits per channel as RGB, the alpha channel is dropped
+    pub fn load(path: &Path) -> io::Result<ColorMap<[u8; 3]>> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+
+        let channels = info.color_type.samples();
+        let data = buf[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|pixel| match channels {
+                1 | 2 => [pixel[0]; 3],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            })
+            .collect();
+
+        Ok(ColorMap {
+            width: info.width as usize,
+            height: info.height as usize,
+            data,
+        })
+    }
+
+    ///Write an RGB png strip by strip, `strip` gets the first row and the row count and returns those rows.
+    ///
+    ///Only a single strip is kept in memory, no matter how big the image is.
+    pub fn save_strips(
+        path: &Path,
+        width: usize,
+        height: usize,
+        strip_height: usize,
+        mut strip: impl FnMut(usize, usize) -> ColorMap<[u8; 3]>,
+    ) -> io::Result<()> {
+        let file = File::create(path)?;
+        let w = BufWriter::new(file);
+
+        let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?.into_stream_writer()?;
+        for y in (0..height).step_by(strip_height.max(1)) {
+            let rows = strip_height.min(height - y);
+            writer.write_all(strip(y, rows).data.concat().as_slice())?;
+        }
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    ///Render a set strip by strip straight into a png, memory use only depends on the width
+    pub fn render_streaming(path: &Path, set: &Mandelbrot, strip_height: usize) -> io::Result<()> {
+        let (width, height) = (set.image_size.x, set.image_size.y);
+
+        let mut strips = Strips::new(set);
+        save_strips(path, width, height, strip_height, |y, rows| {
+            strips.next(y, rows).map(|&iter| set.color(iter))
+        })
+    }
+
     #[allow(dead_code)]
-    pub fn to_binary(yx_map: &Vec<Vec<Color>>) -> Vec<u8> {
+    pub fn to_binary(yx_map: &[Vec<[u8; 3]>]) -> Vec<u8> {
         //Without multithreading
-        let mut data: Vec<u8> = Vec::with_capacity(DATA_SIZE);
+        let size = yx_map.iter().map(|x_vec| x_vec.len() * 4).sum();
+        let mut data: Vec<u8> = Vec::with_capacity(size);
         //let yx_map = transpose::yx_map(yx_map);
 
         for x_vec in yx_map {
             for color in x_vec {
                 //Get bytes
-                let mut bytes = color.to_vec();
+                data.extend_from_slice(color);
+                data.push(255);
+            }
+        }
+
+        data
+    }
+}
 
-                data.append(&mut bytes);
+pub mod ppm {
+    use super::*;
+
+    ///Save a color map as binary ppm, which needs no encoding at all
+    pub fn save(path: &Path, map: &ColorMap<[u8; 3]>) -> io::Result<()> {
+        let file = File::create(path)?;
+        let mut w = BufWriter::new(file);
+
+        write!(w, "P6\n{} {}\n255\n", map.width, map.height)?;
+        for color in &map.data {
+            w.write_all(color)?;
+        }
+        w.flush()
+    }
+
+    ///Write a ppm strip by strip, works like `png_crate::save_strips`
+    pub fn save_strips(
+        path: &Path,
+        width: usize,
+        height: usize,
+        strip_height: usize,
+        mut strip: impl FnMut(usize, usize) -> ColorMap<[u8; 3]>,
+    ) -> io::Result<()> {
+        let file = File::create(path)?;
+        let mut w = BufWriter::new(file);
+
+        write!(w, "P6\n{} {}\n255\n", width, height)?;
+        for y in (0..height).step_by(strip_height.max(1)) {
+            let rows = strip_height.min(height - y);
+            w.write_all(strip(y, rows).data.concat().as_slice())?;
+        }
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::Dim;
+
+    fn gradient(width: usize, height: usize) -> ColorMap<[u8; 3]> {
+        let mut map = ColorMap::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                map[(x, y)] = [x as u8, y as u8, (x * y) as u8];
             }
         }
+        map
+    }
+
+    #[test]
+    fn strips_cover_every_row_once() {
+        let dir = TempDir::new("data");
+        let path = dir.join("strips.png");
+        let map = gradient(30, 23);
+
+        let mut requested = Vec::new();
+        png_crate::save_strips(&path, 30, 23, 10, |y, rows| {
+            requested.push((y, rows));
+            map.crop(0, y, 30, rows)
+        })
+        .unwrap();
+
+        assert_eq!(requested, [(0, 10), (10, 10), (20, 3)]);
+        assert_eq!(png_crate::load(&path).unwrap().data, map.data);
+    }
+
+    #[test]
+    fn streaming_matches_a_whole_render() {
+        let dir = TempDir::new("data");
+        let path = dir.join("streaming.png");
+        let set =
+            Mandelbrot::from_range(Dim::new(40, 25), (-2., 0.5), (-0.2, 1.)).max_iterations(100);
+
+        png_crate::render_streaming(&path, &set, 7).unwrap();
+        let expected = set.iteration_map().map(|&iter| set.color(iter));
+        assert_eq!(png_crate::load(&path).unwrap().data, expected.data);
+    }
 
-        data[0..DATA_SIZE].to_vec()
+    #[test]
+    fn ppm_strips_match_a_whole_image() {
+        let dir = TempDir::new("data");
+        let (whole, strips) = (dir.join("whole.ppm"), dir.join("strips.ppm"));
+        let map = gradient(17, 9);
+
+        ppm::save(&whole, &map).unwrap();
+        ppm::save_strips(&strips, 17, 9, 4, |y, rows| map.crop(0, y, 17, rows)).unwrap();
+        assert_eq!(
+            std::fs::read(&whole).unwrap(),
+            std::fs::read(&strips).unwrap()
+        );
+        assert!(std::fs::read(&whole)
+            .unwrap()
+            .starts_with(b"P6\n17 9\n255\n"));
     }
 }
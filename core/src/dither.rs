@@ -0,0 +1,374 @@
+//!Dithering of linear colors into 8 bit sRGB, against banding in smooth gradients.
+//!
+//!The result of a pixel only depends on its position in the whole image and on the colors
+//!of its block, so strips and tiles that cover whole blocks match a render of the full image.
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::ColorMap;
+
+///Side of the blue noise texture and of the error diffusion blocks
+pub const BLOCK: usize = 64;
+
+///How linear colors get quantized
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    ///Round to the nearest value
+    None,
+    ///Threshold from a tiled blue noise texture, noise without visible patterns
+    BlueNoise,
+    ///Error diffusion inside every block of the image
+    FloydSteinberg,
+}
+
+impl Dither {
+    pub const ALL: [Dither; 3] = [Dither::None, Dither::BlueNoise, Dither::FloydSteinberg];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dither::None => "none",
+            Dither::BlueNoise => "blue-noise",
+            Dither::FloydSteinberg => "floyd-steinberg",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|d| d.name() == name)
+    }
+
+    ///Quantize linear colors from 0 to 1 of a region, `x` and `y` are its position in the whole image
+    pub fn quantize(self, colors: &ColorMap<[f32; 3]>, x: usize, y: usize) -> ColorMap<[u8; 3]> {
+        let srgb: Vec<[f32; 3]> = colors
+            .data
+            .par_iter()
+            .map(|c| c.map(|c| encode(c) * 255.))
+            .collect();
+
+        let data = match self {
+            Dither::None => srgb.par_iter().map(|c| c.map(quantize)).collect(),
+            Dither::BlueNoise => blue_noise_dither(&srgb, colors.width, x, y),
+            Dither::FloydSteinberg => floyd_steinberg(&srgb, colors.width, x, y),
+        };
+
+        ColorMap {
+            width: colors.width,
+            height: colors.height,
+            data,
+        }
+    }
+}
+
+///Smallest region of whole blocks around a region, limited to the image size.
+///
+///Error diffusion of a strip or tile has to cover this region to match the full image.
+pub fn block_region(
+    (x, y): (usize, usize),
+    (width, height): (usize, usize),
+    image: (usize, usize),
+) -> ((usize, usize), (usize, usize)) {
+    let (x0, y0) = (x / BLOCK * BLOCK, y / BLOCK * BLOCK);
+    let x1 = ((x + width).div_ceil(BLOCK) * BLOCK).min(image.0);
+    let y1 = ((y + height).div_ceil(BLOCK) * BLOCK).min(image.1);
+
+    ((x0, y0), (x1.saturating_sub(x0), y1.saturating_sub(y0)))
+}
+
+///sRGB transfer function, from linear light to 0 to 1
+fn encode(c: f32) -> f32 {
+    let c = c.clamp(0., 1.);
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+fn quantize(value: f32) -> u8 {
+    value.round().clamp(0., 255.) as u8
+}
+
+///Deterministic random number from 0 to 1
+fn random(i: u64) -> f64 {
+    //splitmix64
+    let mut z = i.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+
+    (z >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+///Thresholds from 0 to 1 of a `BLOCK` x `BLOCK` blue noise texture that tiles seamlessly.
+///
+///Made with the void and cluster method, it is only generated once.
+fn blue_noise() -> &'static [f32] {
+    static TEXTURE: OnceLock<Vec<f32>> = OnceLock::new();
+
+    TEXTURE.get_or_init(|| {
+        let size = BLOCK * BLOCK;
+
+        //Gaussian weight of every offset, wrapping around the borders
+        let kernel: Vec<f32> = (0..size)
+            .map(|i| {
+                let wrap = |d: usize| d.min(BLOCK - d) as f32;
+                let (dx, dy) = (wrap(i % BLOCK), wrap(i / BLOCK));
+                (-(dx * dx + dy * dy) / (2. * 1.5 * 1.5)).exp()
+            })
+            .collect();
+
+        //How crowded the surroundings of every pixel are
+        let mut energy = vec![0_f32; size];
+        let mut ones = vec![false; size];
+        let toggle = |ones: &mut Vec<bool>, energy: &mut Vec<f32>, p: usize| {
+            ones[p] = !ones[p];
+            let sign = if ones[p] { 1. } else { -1. };
+            let (px, py) = (p % BLOCK, p / BLOCK);
+
+            for (i, e) in energy.iter_mut().enumerate() {
+                let dx = (i % BLOCK + BLOCK - px) % BLOCK;
+                let dy = (i / BLOCK + BLOCK - py) % BLOCK;
+                *e += sign * kernel[dx + dy * BLOCK];
+            }
+        };
+        //Most crowded one and emptiest zero, the first index wins ties
+        let tightest = |ones: &[bool], energy: &[f32]| {
+            (0..size)
+                .filter(|&i| ones[i])
+                .max_by(|&a, &b| energy[a].total_cmp(&energy[b]).then(b.cmp(&a)))
+                .unwrap()
+        };
+        let largest_void = |ones: &[bool], energy: &[f32]| {
+            (0..size)
+                .filter(|&i| !ones[i])
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]).then(a.cmp(&b)))
+                .unwrap()
+        };
+
+        //Random start with a tenth of the pixels set
+        let initial = size / 10;
+        let mut i = 0;
+        while ones.iter().filter(|&&one| one).count() < initial {
+            let p = (random(i) * size as f64) as usize;
+            if !ones[p] {
+                toggle(&mut ones, &mut energy, p);
+            }
+            i += 1;
+        }
+
+        //Move points from clusters into voids until that changes nothing
+        for _ in 0..size {
+            let cluster = tightest(&ones, &energy);
+            toggle(&mut ones, &mut energy, cluster);
+            let void = largest_void(&ones, &energy);
+            toggle(&mut ones, &mut energy, void);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut rank = vec![0; size];
+
+        //Rank the start points by removing the most crowded one first
+        let (mut start_ones, mut start_energy) = (ones.clone(), energy.clone());
+        for r in (0..initial).rev() {
+            let cluster = tightest(&start_ones, &start_energy);
+            toggle(&mut start_ones, &mut start_energy, cluster);
+            rank[cluster] = r;
+        }
+
+        //Then fill the emptiest spot until every pixel has a rank
+        for r in initial..size {
+            let void = largest_void(&ones, &energy);
+            toggle(&mut ones, &mut energy, void);
+            rank[void] = r;
+        }
+
+        rank.into_iter()
+            .map(|r| (r as f32 + 0.5) / size as f32)
+            .collect()
+    })
+}
+
+fn blue_noise_dither(srgb: &[[f32; 3]], width: usize, x: usize, y: usize) -> Vec<[u8; 3]> {
+    let texture = blue_noise();
+
+    srgb.par_iter()
+        .enumerate()
+        .map(|(i, color)| {
+            let (px, py) = ((x + i % width) % BLOCK, (y + i / width) % BLOCK);
+            let threshold = texture[px + py * BLOCK];
+            color.map(|c| (c + threshold - 0.5).round().clamp(0., 255.) as u8)
+        })
+        .collect()
+}
+
+///Error diffusion that never crosses a block border, rows go back and forth
+fn floyd_steinberg(srgb: &[[f32; 3]], width: usize, x: usize, y: usize) -> Vec<[u8; 3]> {
+    let height = srgb.len() / width.max(1);
+    let mut data = vec![[0; 3]; srgb.len()];
+
+    //Rows of the region that belong to the same row of blocks
+    let mut bands = Vec::new();
+    let mut rest = data.as_mut_slice();
+    let mut row = 0;
+    while row < height {
+        let rows = (BLOCK - (y + row) % BLOCK).min(height - row);
+        let (band, remaining) = rest.split_at_mut(rows * width);
+        bands.push((row, rows, band));
+        rest = remaining;
+        row += rows;
+    }
+
+    bands.into_par_iter().for_each(|(first_row, rows, band)| {
+        let mut column = 0;
+        while column < width {
+            let columns = (BLOCK - (x + column) % BLOCK).min(width - column);
+
+            //Error of the current and the next row, with a margin on both sides
+            let mut error = vec![[0_f32; 3]; columns + 2];
+            let mut next = vec![[0_f32; 3]; columns + 2];
+
+            for row in 0..rows {
+                //Direction depends on the row of the whole image, like the blocks
+                let forward = (y + first_row + row) & 1 == 0;
+
+                for step in 0..columns {
+                    let local = if forward { step } else { columns - 1 - step };
+                    let i = (first_row + row) * width + column + local;
+
+                    let mut quantized = [0; 3];
+                    for c in 0..3 {
+                        let value = srgb[i][c] + error[local + 1][c];
+                        quantized[c] = quantize(value);
+                        let e = value - quantized[c] as f32;
+
+                        //Neighbors outside the block are in the margin and get dropped
+                        let (ahead, behind) = if forward {
+                            (local + 2, local)
+                        } else {
+                            (local, local + 2)
+                        };
+                        if step + 1 < columns {
+                            error[ahead][c] += e * 7. / 16.;
+                        }
+                        next[behind][c] += e * 3. / 16.;
+                        next[local + 1][c] += e * 5. / 16.;
+                        next[ahead][c] += e * 1. / 16.;
+                    }
+                    band[row * width + column + local] = quantized;
+                }
+
+                error = std::mem::replace(&mut next, vec![[0.; 3]; columns + 2]);
+                error[0] = [0.; 3];
+                error[columns + 1] = [0.; 3];
+            }
+
+            column += columns;
+        }
+    });
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hdr::ToneMapper;
+    use crate::{color, Dim, Mandelbrot};
+
+    ///Horizontal gradient that spans only a few 8 bit values
+    fn gradient(width: usize, height: usize) -> ColorMap<[f32; 3]> {
+        let (dark, bright) = (color::to_linear([100; 3])[0], color::to_linear([104; 3])[0]);
+        ColorMap {
+            width,
+            height,
+            data: (0..width * height)
+                .map(|i| [dark + (bright - dark) * (i % width) as f32 / width as f32; 3])
+                .collect(),
+        }
+    }
+
+    fn mean(colors: &ColorMap<[u8; 3]>) -> f64 {
+        colors.data.iter().map(|c| c[0] as f64).sum::<f64>() / colors.data.len() as f64
+    }
+
+    #[test]
+    fn dithering_changes_smooth_gradients() {
+        let colors = gradient(200, 130);
+        let rounded = Dither::None.quantize(&colors, 0, 0);
+        let exact = colors
+            .data
+            .iter()
+            .map(|c| encode(c[0]) as f64 * 255.)
+            .sum::<f64>()
+            / colors.data.len() as f64;
+
+        for dither in [Dither::BlueNoise, Dither::FloydSteinberg] {
+            let dithered = dither.quantize(&colors, 0, 0);
+            assert_ne!(dithered.data, rounded.data, "{dither:?}");
+
+            //Keeps the average brightness
+            assert!((mean(&dithered) - exact).abs() < 0.05, "{dither:?}");
+            //But only dithering mixes values within a column
+            let column: Vec<u8> = (0..130).map(|y| dithered[(25, y)][0]).collect();
+            assert!(column.iter().any(|&v| v != column[0]), "{dither:?}");
+        }
+    }
+
+    #[test]
+    fn dithering_changes_rendered_images() {
+        let set = Mandelbrot::from_range(Dim::new(160, 120), (-0.8, -0.7), (0.05, 0.125))
+            .palette(color::Palette::Grayscale);
+        let rounded = set.iteration_map().map(|&iter| set.color(iter));
+
+        for dither in [Dither::BlueNoise, Dither::FloydSteinberg] {
+            let dithered = ToneMapper::default().dither(dither).map(&set.linear_map());
+            assert_ne!(dithered.data, rounded.data, "{dither:?}");
+        }
+        let none = ToneMapper::default().map(&set.linear_map());
+        assert_eq!(none.data, rounded.data);
+    }
+
+    #[test]
+    fn regions_match_the_whole_image() {
+        let colors = gradient(200, 150);
+
+        for dither in Dither::ALL {
+            let whole = dither.quantize(&colors, 0, 0);
+
+            let ((x, y), (width, height)) = block_region((70, 100), (90, 30), (200, 150));
+            assert_eq!(((x, y), (width, height)), ((64, 64), (128, 86)));
+            let region = dither.quantize(&colors.crop(x, y, width, height), x, y);
+            assert_eq!(
+                region.crop(6, 36, 90, 30).data,
+                whole.crop(70, 100, 90, 30).data,
+                "{dither:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn blue_noise_uses_every_threshold_once() {
+        let mut texture = blue_noise().to_vec();
+        texture.sort_by(f32::total_cmp);
+
+        let size = BLOCK * BLOCK;
+        for (rank, threshold) in texture.into_iter().enumerate() {
+            assert_eq!(threshold, (rank as f32 + 0.5) / size as f32);
+        }
+    }
+
+    #[test]
+    fn rounds_without_dithering() {
+        let colors = ColorMap {
+            width: 3,
+            height: 1,
+            data: vec![[0.; 3], [1.; 3], [2.; 3]],
+        };
+        let quantized = Dither::None.quantize(&colors, 0, 0);
+        assert_eq!(quantized.data, vec![[0; 3], [255; 3], [255; 3]]);
+    }
+}
@@ -0,0 +1,221 @@
+//!Exponential map, a log-polar render of a whole zoom into a single strip.
+//!
+//!Every column is an angle around the zoom center and every row a radius, shrinking
+//!exponentially from the corners of the first frame down to a pixel of the last one.
+//!Rows and columns cover the same relative distance, so a pixel has the same detail everywhere.
+
+use rayon::prelude::*;
+use std::f64::consts::TAU;
+
+use crate::animation::DEFAULT_WIDTH;
+use crate::{color, ColorMap, Dim, Mandelbrot};
+
+///Zoom into a single point, frames have the image size of the set
+#[derive(Clone)]
+pub struct ExpMap {
+    ///Iterations, fractal, colors and frame size
+    pub set: Mandelbrot,
+    pub center: (f64, f64),
+    ///Zoom of the first frame, relative to the whole set
+    pub start_zoom: f64,
+    ///Zoom of the last frame
+    pub end_zoom: f64,
+}
+
+impl ExpMap {
+    pub fn new(set: Mandelbrot, center: (f64, f64), start_zoom: f64, end_zoom: f64) -> Self {
+        Self {
+            set,
+            center,
+            start_zoom,
+            end_zoom,
+        }
+    }
+
+    ///Size of a frame pixel at a zoom level
+    fn pixel_size(&self, zoom: f64) -> f64 {
+        DEFAULT_WIDTH / zoom / self.set.image_size.x as f64
+    }
+
+    ///Distance from the center to the corners of the first frame
+    fn outer_radius(&self) -> f64 {
+        let size = self.set.image_size;
+        (size.x as f64).hypot(size.y as f64) / 2. * self.pixel_size(self.start_zoom)
+    }
+
+    ///Half a pixel of the last frame, everything closer to the center is never visible
+    fn inner_radius(&self) -> f64 {
+        self.pixel_size(self.end_zoom) / 2.
+    }
+
+    ///Columns of the strip, enough for a full resolution circle through the frame corners
+    fn columns(&self) -> usize {
+        let size = self.set.image_size;
+        let half_diagonal = (size.x as f64).hypot(size.y as f64) / 2.;
+        ((TAU * half_diagonal).ceil() as usize).max(4)
+    }
+
+    ///Size of the whole strip, the width is the full circle and the height the zoom depth
+    pub fn size(&self) -> Dim<usize> {
+        let columns = self.columns();
+        let depth = (self.outer_radius() / self.inner_radius()).ln().max(0.);
+        let rows = (depth * columns as f64 / TAU).ceil() as usize + 1;
+
+        Dim::new(columns, rows)
+    }
+
+    ///Point in the complex plane of a strip position, row 0 touches the corners of the first frame
+    pub fn point(&self, x: f64, y: f64) -> (f64, f64) {
+        let step = TAU / self.columns() as f64;
+        let radius = self.outer_radius() * (-y * step).exp();
+        let (sin, cos) = (x * step).sin_cos();
+
+        (self.center.0 + radius * cos, self.center.1 + radius * sin)
+    }
+
+    ///Iteration counts of some rows of the strip
+    pub fn region(&self, y: usize, rows: usize) -> ColorMap<u64> {
+        let width = self.columns();
+        let mut map = ColorMap::new(width, rows);
+
+        map.data
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(row_y, row)| {
+                for (x, iter) in row.iter_mut().enumerate() {
+                    let (x0, y0) = self.point(x as f64, (y + row_y) as f64);
+                    *iter = self.set.iterate(x0, y0);
+                }
+            });
+
+        map
+    }
+
+    ///Colors of the whole strip
+    pub fn render(&self) -> ColorMap<[u8; 3]> {
+        let size = self.size();
+        self.region(0, size.y).map(|&iter| self.set.color(iter))
+    }
+
+    ///Zoom of a frame, frames are spaced evenly in zoom depth
+    pub fn zoom(&self, frame: usize, frames: usize) -> f64 {
+        let t = frame as f64 / (frames.max(2) - 1) as f64;
+        self.start_zoom * (self.end_zoom / self.start_zoom).powf(t)
+    }
+
+    ///Unwrap the rendered strip into an ordinary frame at a zoom level.
+    ///
+    ///Pixels close to the center come from deeper rows, which hold the detail of later frames.
+    pub fn frame(&self, strip: &ColorMap<[u8; 3]>, zoom: f64) -> ColorMap<[u8; 3]> {
+        let (width, height) = (self.set.image_size.x, self.set.image_size.y);
+        let pixel_size = self.pixel_size(zoom);
+
+        let columns = strip.width as f64;
+        let step = TAU / columns;
+        let outer_radius = self.outer_radius();
+
+        //Blend in linear light, like the antialiasing
+        let sample = |x: usize, y: usize| color::to_linear(strip[(x % strip.width, y)]);
+
+        let mut map = ColorMap::new(width, height);
+        map.data
+            .par_chunks_mut(width.max(1))
+            .enumerate()
+            .for_each(|(py, row)| {
+                for (px, color) in row.iter_mut().enumerate() {
+                    //Same position as in `Mandelbrot::get_pixel`
+                    let dx = (px as f64 - (width / 2) as f64) * pixel_size;
+                    let dy = (py as f64 - (height / 2) as f64) * pixel_size;
+                    let radius = dx.hypot(dy).max(f64::MIN_POSITIVE);
+
+                    let x = (dy.atan2(dx) / step).rem_euclid(columns);
+                    let y =
+                        ((outer_radius / radius).ln() / step).clamp(0., (strip.height - 1) as f64);
+
+                    //Bilinear, the angle wraps around
+                    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+                    let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+                    let y1 = (y0 + 1).min(strip.height - 1);
+
+                    let (a, b) = (sample(x0, y0), sample(x0 + 1, y0));
+                    let (c, d) = (sample(x0, y1), sample(x0 + 1, y1));
+                    let blended = [0, 1, 2].map(|i| {
+                        let top = a[i] + (b[i] - a[i]) * fx;
+                        let bottom = c[i] + (d[i] - c[i]) * fx;
+                        top + (bottom - top) * fy
+                    });
+
+                    *color = color::from_linear(blended);
+                }
+            });
+
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exp_map(end_zoom: f64) -> ExpMap {
+        let set = Mandelbrot::from_range(Dim::new(8, 6), (-2., 0.5), (-1., 1.)).max_iterations(100);
+        ExpMap::new(set, (-0.75, 0.1), 1., end_zoom)
+    }
+
+    #[test]
+    fn rows_grow_with_the_zoom_depth() {
+        //Circle through the corners of an 8x6 frame
+        let size = exp_map(1000.).size();
+        assert_eq!(size.x, 32);
+
+        //Every row is a step of 2π/32 in log radius
+        let deeper = exp_map(2000.).size();
+        let expected = 2_f64.ln() * 32. / TAU;
+        assert!((deeper.y as f64 - size.y as f64 - expected).abs() <= 1.);
+        assert!(exp_map(1.).size().y < size.y);
+    }
+
+    #[test]
+    fn points_spiral_into_the_center() {
+        let exp_map = exp_map(1000.);
+        let distance = |(x, y): (f64, f64)| (x - -0.75).hypot(y - 0.1);
+
+        let corner = exp_map.outer_radius();
+        assert!((distance(exp_map.point(0., 0.)) - corner).abs() < 1e-12);
+        assert!((exp_map.point(0., 0.).0 - (-0.75 + corner)).abs() < 1e-12);
+        assert!((exp_map.point(8., 0.).1 - (0.1 + corner)).abs() < 1e-12);
+
+        let step = TAU / 32.;
+        assert!((distance(exp_map.point(3., 10.)) - corner * (-10. * step).exp()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn frames_are_spaced_evenly_in_depth() {
+        let exp_map = exp_map(10_000.);
+        assert_eq!(exp_map.zoom(0, 5), 1.);
+        assert!((exp_map.zoom(2, 5) - 100.).abs() < 1e-9);
+        assert!((exp_map.zoom(4, 5) - 10_000.).abs() < 1e-9);
+        assert_eq!(exp_map.zoom(0, 1), 1.);
+    }
+
+    #[test]
+    fn frames_unwrap_the_angles() {
+        let exp_map = exp_map(1000.);
+        let size = exp_map.size();
+        assert_eq!(exp_map.render().data.len(), size.x * size.y);
+
+        //Every column has its own color
+        let mut strip = ColorMap::new(size.x, size.y);
+        for y in 0..size.y {
+            for x in 0..size.x {
+                strip[(x, y)] = [x as u8 * 8, 0, 0];
+            }
+        }
+
+        let frame = exp_map.frame(&strip, 10.);
+        assert_eq!((frame.width, frame.height), (8, 6));
+        //Right of the center is column 0, below it a quarter turn
+        assert_eq!(frame[(7, 3)], [0, 0, 0]);
+        assert_eq!(frame[(4, 5)], [64, 0, 0]);
+    }
+}
@@ -0,0 +1,364 @@
+//!High dynamic range colors, linear light `f32` RGB that can go beyond 1.
+//!
+//!Colors stay linear until the output. They are either saved as they are in a Radiance `.hdr`
+//!file, or tone mapped into the displayable range and quantized to 8 bit sRGB.
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::dither::Dither;
+use crate::{color, ColorMap};
+
+///Curve that compresses linear colors into the range from 0 to 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToneMap {
+    ///Cut off everything above 1, keeps colors in range unchanged
+    Clamp,
+    ///Reinhard on the luminance, keeps the hue of bright colors
+    Reinhard,
+    ///Filmic curve of the ACES reference transform, fitted by Krzysztof Narkowicz
+    Aces,
+}
+
+impl ToneMap {
+    pub const ALL: [ToneMap; 3] = [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::Aces];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMap::Clamp => "clamp",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::Aces => "aces",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+///Luminance of a linear sRGB color
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+///Turns linear colors into displayable ones
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapper {
+    pub tone_map: ToneMap,
+    ///Brightness change before the tone mapping in stops, every stop doubles the light
+    pub exposure: f32,
+    ///Quantization of whole images, single colors are always rounded
+    pub dither: Dither,
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        Self::new(ToneMap::Clamp)
+    }
+}
+
+impl ToneMapper {
+    pub fn new(tone_map: ToneMap) -> Self {
+        Self {
+            tone_map,
+            exposure: 0.,
+            dither: Dither::None,
+        }
+    }
+
+    pub fn exposure(mut self, stops: f32) -> Self {
+        self.exposure = stops;
+        self
+    }
+
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    ///Linear color from 0 to 1
+    pub fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+        let scale = self.exposure.exp2();
+        //Negative values and NaN become 0
+        let color = color.map(|c| (c * scale).max(0.));
+
+        let mapped = match self.tone_map {
+            ToneMap::Clamp => color,
+            ToneMap::Reinhard => {
+                let l = luminance(color);
+                color.map(|c| c / (1. + l))
+            }
+            ToneMap::Aces => {
+                color.map(|c| (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14))
+            }
+        };
+        mapped.map(|c| c.clamp(0., 1.))
+    }
+
+    ///Tone mapped 8 bit sRGB color
+    pub fn quantize(&self, color: [f32; 3]) -> [u8; 3] {
+        color::from_linear(self.apply(color))
+    }
+
+    ///Tone map and quantize a whole image
+    pub fn map(&self, colors: &ColorMap<[f32; 3]>) -> ColorMap<[u8; 3]> {
+        self.map_region(colors, 0, 0)
+    }
+
+    ///Tone map and quantize a region at `x` and `y` of the image, the dithering depends on the position
+    pub fn map_region(&self, colors: &ColorMap<[f32; 3]>, x: usize, y: usize) -> ColorMap<[u8; 3]> {
+        let mapped = ColorMap {
+            width: colors.width,
+            height: colors.height,
+            data: colors.data.par_iter().map(|&c| self.apply(c)).collect(),
+        };
+        self.dither.quantize(&mapped, x, y)
+    }
+}
+
+///Radiance RGBE images, every pixel is 8 bit RGB with a shared exponent
+pub mod radiance {
+    use super::*;
+
+    ///Mantissas of the three channels and their common exponent
+    fn rgbe(color: [f32; 3]) -> [u8; 4] {
+        let color = color.map(|c| c.max(0.));
+        let max = color[0].max(color[1]).max(color[2]);
+        if max < 1e-32 {
+            return [0; 4];
+        }
+
+        //Largest channel gets a mantissa from 128 to 255
+        let exponent = max.log2().floor() as i32 + 1;
+        let scale = 256. / (exponent as f32).exp2();
+        let [r, g, b] = color.map(|c| (c * scale) as u8);
+        [r, g, b, (exponent + 128) as u8]
+    }
+
+    ///Run length encoding of one channel of a scanline, runs of at least 4 equal bytes are packed
+    fn encode_channel(out: &mut Vec<u8>, data: &[u8]) {
+        let mut i = 0;
+
+        while i < data.len() {
+            //Find the next run
+            let (mut start, mut run) = (i, 0);
+            while start < data.len() {
+                run = 1;
+                while start + run < data.len() && run < 127 && data[start + run] == data[start] {
+                    run += 1;
+                }
+                if run >= 4 {
+                    break;
+                }
+                start += run;
+            }
+
+            //Bytes before the run as they are
+            while i < start {
+                let count = (start - i).min(128);
+                out.push(count as u8);
+                out.extend_from_slice(&data[i..i + count]);
+                i += count;
+            }
+
+            if start < data.len() {
+                out.extend_from_slice(&[128 + run as u8, data[start]]);
+                i = start + run;
+            }
+        }
+    }
+
+    ///Encode an image with run length encoded scanlines
+    pub fn write<W: Write>(mut w: W, colors: &ColorMap<[f32; 3]>) -> io::Result<()> {
+        let (width, height) = (colors.width, colors.height);
+        write!(
+            w,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n"
+        )?;
+
+        let rows: Vec<Vec<u8>> = colors
+            .data
+            .par_chunks(width.max(1))
+            .map(|row| {
+                let pixels: Vec<[u8; 4]> = row.iter().map(|&c| rgbe(c)).collect();
+
+                //Run length encoding only exists for these widths
+                if !(8..=0x7fff).contains(&width) {
+                    return pixels.concat();
+                }
+
+                let mut out = vec![2, 2, (width >> 8) as u8, width as u8];
+                for channel in 0..4 {
+                    let data: Vec<u8> = pixels.iter().map(|p| p[channel]).collect();
+                    encode_channel(&mut out, &data);
+                }
+                out
+            })
+            .collect();
+
+        for row in rows {
+            w.write_all(&row)?;
+        }
+        w.flush()
+    }
+
+    pub fn save(path: &Path, colors: &ColorMap<[f32; 3]>) -> io::Result<()> {
+        write(BufWriter::new(File::create(path)?), colors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dim, Mandelbrot};
+
+    ///Linear color of an RGBE pixel, at the center of its range
+    fn decode([r, g, b, e]: [u8; 4]) -> [f32; 3] {
+        if e == 0 {
+            return [0.; 3];
+        }
+        let scale = (e as f32 - 136.).exp2();
+        [r, g, b].map(|m| (m as f32 + 0.5) * scale)
+    }
+
+    ///Scanlines of a Radiance image, undoing the run length encoding
+    fn read(bytes: &[u8], width: usize) -> Vec<[u8; 4]> {
+        let mut pixels = Vec::new();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            if width < 8 {
+                pixels.push(bytes[i..i + 4].try_into().unwrap());
+                i += 4;
+                continue;
+            }
+
+            assert_eq!(&bytes[i..i + 4], [2, 2, (width >> 8) as u8, width as u8]);
+            i += 4;
+            let mut channels = vec![Vec::new(); 4];
+            for channel in &mut channels {
+                while channel.len() < width {
+                    let count = bytes[i] as usize;
+                    if count > 128 {
+                        channel.extend(std::iter::repeat_n(bytes[i + 1], count - 128));
+                        i += 2;
+                    } else {
+                        channel.extend_from_slice(&bytes[i + 1..i + 1 + count]);
+                        i += 1 + count;
+                    }
+                }
+                assert_eq!(channel.len(), width);
+            }
+            pixels.extend((0..width).map(|x| [0, 1, 2, 3].map(|c| channels[c][x])));
+        }
+
+        pixels
+    }
+
+    fn save(colors: &ColorMap<[f32; 3]>) -> (String, Vec<[u8; 4]>) {
+        let mut bytes = Vec::new();
+        radiance::write(&mut bytes, colors).unwrap();
+
+        let end = bytes.windows(2).position(|w| w == b"\n\n").unwrap() + 2;
+        let end = end + bytes[end..].iter().position(|&b| b == b'\n').unwrap() + 1;
+        let header = String::from_utf8(bytes[..end].to_vec()).unwrap();
+        (header, read(&bytes[end..], colors.width))
+    }
+
+    #[test]
+    fn radiance_keeps_bright_and_dark_colors() {
+        for width in [4, 300] {
+            //Runs, single values and colors far outside of 0 to 1
+            let colors = ColorMap {
+                width,
+                height: 2,
+                data: (0..width * 2)
+                    .map(|i| match i % 7 {
+                        0..=3 => [5., 0.5, 0.],
+                        4 => [0.; 3],
+                        _ => [i as f32 * 0.01, 0.0001 * i as f32, 40.],
+                    })
+                    .collect(),
+            };
+
+            let (header, pixels) = save(&colors);
+            assert!(header.starts_with("#?RADIANCE\n"));
+            assert!(header.ends_with(&format!("-Y 2 +X {width}\n")));
+            assert_eq!(pixels.len(), colors.data.len());
+
+            for (pixel, color) in pixels.iter().zip(&colors.data) {
+                let max = color.iter().copied().fold(0., f32::max);
+                for (a, b) in decode(*pixel).iter().zip(color) {
+                    assert!((a - b).abs() <= max / 128., "{a} {b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn radiance_output_is_not_quantized() {
+        let set = Mandelbrot::from_range(Dim::new(64, 32), (-2., 0.47), (-1.12, 1.12))
+            .palette(crate::color::Palette::Grayscale);
+        let colors = set.linear_map();
+        let (_, pixels) = save(&colors);
+
+        //Every pixel keeps its relative precision, 8 bit sRGB loses it in dark colors
+        let mut coarser = 0;
+        for (color, pixel) in colors.data.iter().zip(&pixels) {
+            let (color, decoded) = (color[0], decode(*pixel)[0]);
+            assert!((color - decoded).abs() <= color / 128.);
+
+            let srgb = crate::color::to_linear(crate::color::from_linear([color; 3]))[0];
+            if (color - srgb).abs() > color / 128. {
+                coarser += 1;
+            }
+        }
+        assert!(coarser > 0);
+    }
+
+    #[test]
+    fn tone_maps_into_range() {
+        let colors = [
+            [0.; 3],
+            [0.25, 0.5, 1.],
+            [4., 16., 0.1],
+            [f32::NAN, -1., 1e9],
+        ];
+        for tone_map in ToneMap::ALL {
+            for exposure in [-2., 0., 3.] {
+                let mapper = ToneMapper::new(tone_map).exposure(exposure);
+                for color in colors {
+                    let mapped = mapper.apply(color);
+                    assert!(mapped.iter().all(|c| (0. ..=1.).contains(c)), "{mapped:?}");
+                }
+            }
+        }
+
+        assert_eq!(
+            ToneMapper::default().apply([0.25, 0.5, 1.]),
+            [0.25, 0.5, 1.]
+        );
+        assert_eq!(
+            ToneMapper::default().exposure(1.).apply([0.25, 0.5, 1.]),
+            [0.5, 1., 1.]
+        );
+        assert_eq!(ToneMapper::default().apply([f32::NAN, -1., 0.]), [0.; 3]);
+    }
+
+    #[test]
+    fn reinhard_keeps_the_hue() {
+        let [r, g, b] = ToneMapper::new(ToneMap::Reinhard).apply([0.8, 0.4, 0.2]);
+        assert!((r / g - 2.).abs() < 1e-5 && (g / b - 2.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn clamp_quantizes_like_the_palette() {
+        let set = Mandelbrot::from_range(Dim::new(32, 16), (-2., 0.47), (-1.12, 1.12));
+        let colors = ToneMapper::default().map(&set.linear_map());
+        assert_eq!(colors.data, set.iteration_map().map(|&i| set.color(i)).data);
+    }
+}
@@ -0,0 +1,622 @@
+//!Kalles Fraktaler locations in `.kfr` files and palettes in `.kfp` files.
+//!
+//!Both are text files with one `Key: value` setting per line. The settings we can render are
+//!mapped onto a location, every other setting that changes the image ends up in a warning.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::animation::DEFAULT_WIDTH;
+use crate::color::Palette;
+use crate::location::Location;
+use crate::{Dim, Fractal};
+
+///Half the height of the view at zoom 1
+const RADIUS: f64 = 2.;
+///Steps of the color cycle that `ColorOffset` counts in
+const PALETTE_STEPS: f32 = 1024.;
+///Colors of an exported palette
+const EXPORT_COLORS: usize = 32;
+///Largest mean difference of a channel for a palette that counts as the same
+const SAME_PALETTE: f32 = 4.;
+///Deeper zooms need more precision than `f64` has
+const MAX_ZOOM: f64 = 1e13;
+
+///Settings we map onto a location
+const MAPPED: [&str; 9] = [
+    "Re",
+    "Im",
+    "Zoom",
+    "Iterations",
+    "Rotate",
+    "FractalType",
+    "Power",
+    "ColorOffset",
+    "Colors",
+];
+
+///Settings that change the image, with the value that doesn't and what they do
+const NEUTRAL: [(&str, &str, &str); 20] = [
+    ("Ratio", "360", "stretching"),
+    ("IterDiv", "1", "color density"),
+    ("Smooth", "0", "smooth coloring"),
+    ("SmoothMethod", "0", "smoothing method"),
+    ("ColorMethod", "0", "color method"),
+    ("Differences", "0", "differences coloring"),
+    ("ColorPhaseStrength", "0", "color phase"),
+    ("InteriorColor", "0,0,0", "interior color"),
+    ("MultiColor", "0", "multi wave coloring"),
+    ("Slopes", "0", "slope shading"),
+    ("TextureEnabled", "0", "texture"),
+    ("SeedR", "0", "Julia seed"),
+    ("SeedI", "0", "Julia seed"),
+    ("FactorAR", "1", "formula factor"),
+    ("FactorAI", "0", "formula factor"),
+    ("real", "1", "real part of the formula"),
+    ("imag", "1", "imaginary part of the formula"),
+    ("UseHybridFormula", "0", "hybrid formula"),
+    ("ExponentialMap", "0", "exponential map"),
+    ("Mirror", "0", "mirroring"),
+];
+
+///Settings that only change speed, accuracy or the window, or that only matter together with a neutral one
+const IRRELEVANT: [&str; 24] = [
+    "Version",
+    "Period",
+    "ZoomSize",
+    "MaxReferences",
+    "GlitchLowTolerance",
+    "ApproxLowTolerance",
+    "AutoApproxTerms",
+    "ApproxTerms",
+    "WindowWidth",
+    "WindowHeight",
+    "WindowTopX",
+    "WindowTopY",
+    "WindowBottomX",
+    "WindowBottomY",
+    "SlopePower",
+    "SlopeRatio",
+    "SlopeAngle",
+    "BlendMC",
+    "MultiColors",
+    "JitterSeed",
+    "TextureMerge",
+    "TexturePower",
+    "TextureRatio",
+    "TextureFile",
+];
+
+///What a Kalles Fraktaler file contains
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    ///`.kfr` with the view and the colors
+    Location,
+    ///`.kfp` with only the colors
+    Palette,
+}
+
+impl Kind {
+    ///Kind of a file by its extension, `None` for other files
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_string_lossy().to_lowercase();
+        match ext.as_str() {
+            "kfr" => Some(Kind::Location),
+            "kfp" => Some(Kind::Palette),
+            _ => None,
+        }
+    }
+}
+
+///Settings in the order of the file
+fn parse(text: &str) -> Vec<(&str, &str)> {
+    text.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+fn get<'a>(settings: &[(&str, &'a str)], key: &str) -> Option<&'a str> {
+    settings.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+}
+
+fn number<T: std::str::FromStr>(settings: &[(&str, &str)], key: &str) -> Result<Option<T>, String> {
+    get(settings, key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("Invalid {key} '{value}'"))
+        })
+        .transpose()
+}
+
+///Whether two values are the same, as numbers if both are numbers
+fn same_value(a: &str, b: &str) -> bool {
+    let (a, b) = (a.trim_end_matches(','), b.trim_end_matches(','));
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.replace(' ', "") == b.replace(' ', ""),
+    }
+}
+
+///Warnings for settings that change the image but weren't mapped
+fn unmapped(settings: &[(&str, &str)], mapped: &[&str]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut unknown = Vec::new();
+
+    for &(key, value) in settings {
+        if mapped.contains(&key) || IRRELEVANT.contains(&key) {
+            continue;
+        }
+        match NEUTRAL.iter().find(|(k, _, _)| *k == key) {
+            Some((_, neutral, _)) if same_value(value, neutral) => {}
+            Some((_, _, what)) => {
+                warnings.push(format!("The {what} ({key}: {value}) is not supported"))
+            }
+            None => unknown.push(key),
+        }
+    }
+
+    if !unknown.is_empty() {
+        warnings.push(format!(
+            "Unknown settings are ignored: {}",
+            unknown.join(", ")
+        ));
+    }
+    warnings
+}
+
+///Colors of a `Colors` list, red, green and blue of every color separated by commas.
+///
+///The channels are in RGB order, the same order Kalles Fraktaler reads its color keys in.
+fn parse_colors(value: &str) -> Option<Vec<[u8; 3]>> {
+    let channels: Vec<u8> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(|c| c.parse().ok())
+        .collect::<Option<_>>()?;
+
+    if channels.is_empty() || !channels.len().is_multiple_of(3) {
+        return None;
+    }
+    Some(channels.chunks(3).map(|c| [c[0], c[1], c[2]]).collect())
+}
+
+///Our palette that is the most similar to a list of colors and the mean difference of a channel
+fn closest_palette(colors: &[[u8; 3]]) -> (Palette, f32) {
+    let difference = |palette: Palette| {
+        let sum: f32 = colors
+            .iter()
+            .enumerate()
+            .map(|(i, color)| {
+                let ours = palette.color(i as f32 / colors.len() as f32);
+                (0..3)
+                    .map(|c| (color[c] as f32 - ours[c] as f32).abs())
+                    .sum::<f32>()
+            })
+            .sum();
+        sum / (colors.len() * 3) as f32
+    };
+
+    Palette::ALL
+        .into_iter()
+        .map(|palette| (palette, difference(palette)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap()
+}
+
+///Map the color settings onto a location
+fn apply_colors(settings: &[(&str, &str)], location: &mut Location) -> Result<Vec<String>, String> {
+    let mut warnings = Vec::new();
+
+    if let Some(offset) = number::<f32>(settings, "ColorOffset")? {
+        location.palette_offset = (offset / PALETTE_STEPS).rem_euclid(1.);
+    }
+
+    if let Some(value) = get(settings, "Colors") {
+        let colors = parse_colors(value).ok_or_else(|| format!("Invalid Colors '{value}'"))?;
+        let (palette, difference) = closest_palette(&colors);
+        location.palette = palette;
+
+        if difference > SAME_PALETTE {
+            warnings.push(format!(
+                "Custom palettes are not supported, the {} colors are replaced by the closest palette '{}'",
+                colors.len(),
+                palette.name()
+            ));
+        }
+    }
+
+    Ok(warnings)
+}
+
+///Location of a kfr file for an image size, with warnings about the settings that didn't map.
+///
+///The zoom of Kalles Fraktaler fits the height of the view, ours the width, so the size matters.
+pub fn import_location(text: &str, size: Dim<usize>) -> Result<(Location, Vec<String>), String> {
+    let settings = parse(text);
+
+    let re = number(&settings, "Re")?.ok_or("The location has no Re")?;
+    let im = number(&settings, "Im")?.ok_or("The location has no Im")?;
+    let zoom: f64 = number(&settings, "Zoom")?.ok_or("The location has no Zoom")?;
+    if !(zoom.is_finite() && zoom > 0.) {
+        return Err(format!("Zoom {zoom:E} is out of range"));
+    }
+
+    let height = 2. * RADIUS / zoom;
+    let width = height * size.x as f64 / size.y as f64;
+    let mut location = Location::new((re, im), DEFAULT_WIDTH / width);
+    let mut warnings = Vec::new();
+
+    if zoom > MAX_ZOOM {
+        warnings.push(format!(
+            "Zoom {zoom:E} needs more precision than 64 bit floats, the image will be blocky"
+        ));
+    }
+    if let Some(iterations) = number::<u64>(&settings, "Iterations")? {
+        if iterations < 1 {
+            return Err("Iterations have to be at least 1".to_string());
+        }
+        location.iterations = iterations;
+    }
+    if let Some(rotation) = number::<f64>(&settings, "Rotate")? {
+        if !rotation.is_finite() {
+            return Err(format!("Invalid Rotate '{rotation}'"));
+        }
+
+        //The same view, but huge angles have lost precision
+        let turned = rotation.rem_euclid(360.);
+        if turned != rotation {
+            warnings.push(format!(
+                "Rotate {rotation} is outside of 0 to 360 degrees, it is turned into {turned}"
+            ));
+        }
+        location.rotation = turned.to_radians();
+    }
+
+    let fractal_type = number(&settings, "FractalType")?.unwrap_or(0);
+    let power = number(&settings, "Power")?.unwrap_or(2);
+    match (fractal_type, power) {
+        (0, 2) => location.fractal = Fractal::Mandelbrot,
+        (1, 2) => location.fractal = Fractal::BurningShip,
+        _ => warnings.push(format!(
+            "Formula {fractal_type} with power {power} is not supported, the Mandelbrot set is rendered instead"
+        )),
+    }
+
+    warnings.extend(apply_colors(&settings, &mut location)?);
+    warnings.extend(unmapped(&settings, &MAPPED));
+    Ok((location, warnings))
+}
+
+///Apply the colors of a kfp file to a location, returns warnings about the settings that didn't map
+pub fn import_palette(text: &str, location: &mut Location) -> Result<Vec<String>, String> {
+    let settings = parse(text);
+
+    let mut warnings = apply_colors(&settings, location)?;
+    warnings.extend(unmapped(&settings, &MAPPED));
+    Ok(warnings)
+}
+
+///Settings of the colors, with the palette sampled into a list of colors
+fn palette_settings(location: &Location) -> String {
+    let colors: Vec<String> = (0..EXPORT_COLORS)
+        .map(|i| location.palette.color(i as f32 / EXPORT_COLORS as f32))
+        .map(|[r, g, b]| format!("{r},{g},{b},"))
+        .collect();
+    let offset = (location.palette_offset * PALETTE_STEPS).round() as u32 % PALETTE_STEPS as u32;
+
+    format!(
+        "Colors: {}\r\nColorOffset: {offset}\r\nIterDiv: 1\r\nSmooth: 0\r\nColorMethod: 0\r\n",
+        colors.concat()
+    )
+}
+
+///Warnings about the colors, they can't look the same
+fn color_warnings(location: &Location) -> Vec<String> {
+    let mut warnings = vec![
+        "Kalles Fraktaler spreads the palette evenly over the iterations, the colors won't match"
+            .to_string(),
+    ];
+    if location.tone_mapper != Default::default() {
+        warnings.push("Tone mapping is not exported".to_string());
+    }
+    warnings
+}
+
+///Text of a kfr file for an image size, with warnings about what wasn't exported
+pub fn export_location(location: &Location, size: Dim<usize>) -> (String, Vec<String>) {
+    let width = DEFAULT_WIDTH / location.zoom;
+    let height = width * size.y as f64 / size.x as f64;
+    let zoom = 2. * RADIUS / height;
+
+    let fractal_type = match location.fractal {
+        Fractal::Mandelbrot => 0,
+        Fractal::BurningShip => 1,
+    };
+
+    let text = format!(
+        "Re: {}\r\nIm: {}\r\nZoom: {zoom:E}\r\nIterations: {}\r\nRotate: {}\r\nFractalType: {fractal_type}\r\nPower: 2\r\n{}",
+        location.center.0,
+        location.center.1,
+        location.iterations,
+        location.rotation.to_degrees().rem_euclid(360.),
+        palette_settings(location),
+    );
+
+    let mut warnings = Vec::new();
+    if location.radius != RADIUS {
+        warnings.push(format!("Escape radius {} is not exported", location.radius));
+    }
+    warnings.extend(color_warnings(location));
+    (text, warnings)
+}
+
+///Text of a kfp file with the colors of a location, with warnings about what wasn't exported
+pub fn export_palette(location: &Location) -> (String, Vec<String>) {
+    (palette_settings(location), color_warnings(location))
+}
+
+fn read(path: &Path) -> io::Result<String> {
+    //Older files are often not UTF-8
+    Ok(String::from_utf8_lossy(&fs::read(path)?).into_owned())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn load_location(path: &Path, size: Dim<usize>) -> io::Result<(Location, Vec<String>)> {
+    import_location(&read(path)?, size).map_err(invalid)
+}
+
+pub fn load_palette(path: &Path, location: &mut Location) -> io::Result<Vec<String>> {
+    import_palette(&read(path)?, location).map_err(invalid)
+}
+
+pub fn save_location(
+    path: &Path,
+    location: &Location,
+    size: Dim<usize>,
+) -> io::Result<Vec<String>> {
+    let (text, warnings) = export_location(location, size);
+    fs::write(path, text)?;
+    Ok(warnings)
+}
+
+pub fn save_palette(path: &Path, location: &Location) -> io::Result<Vec<String>> {
+    let (text, warnings) = export_palette(location);
+    fs::write(path, text)?;
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hdr::{ToneMap, ToneMapper};
+    use crate::test_util::TempDir;
+
+    const SIZE: Dim<usize> = Dim { x: 1920, y: 1080 };
+
+    #[test]
+    fn kinds_from_the_extension() {
+        assert_eq!(Kind::from_path(Path::new("a.KFR")), Some(Kind::Location));
+        assert_eq!(Kind::from_path(Path::new("a.kfp")), Some(Kind::Palette));
+        assert_eq!(Kind::from_path(Path::new("a.toml")), None);
+        assert_eq!(Kind::from_path(Path::new("kfr")), None);
+    }
+
+    #[test]
+    fn imports_the_view() {
+        let text = "Re: -0.75\r\nIm: 0.1\r\nZoom: 1E2\r\nIterations: 5000\r\nRotate: 90\r\nFractalType: 1\r\nPower: 2\r\nVersion: 2.15\r\nSmooth: 0\r\nInteriorColor: 0, 0, 0,\r\n";
+        let (location, warnings) = import_location(text, SIZE).unwrap();
+
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(location.center, (-0.75, 0.1));
+        assert_eq!(location.iterations, 5000);
+        assert_eq!(location.rotation, 90_f64.to_radians());
+        assert_eq!(location.fractal, Fractal::BurningShip);
+
+        //The view is 0.04 high, the width follows from the image size
+        let width = 0.04 * 1920. / 1080.;
+        assert!((location.zoom - DEFAULT_WIDTH / width).abs() < 1e-9);
+    }
+
+    #[test]
+    fn warns_about_unsupported_settings() {
+        let text = "Re: 0\nIm: 0\nZoom: 1E20\nFractalType: 5\nPower: 3\nSmooth: 1\nSlopes: 1\nNewSetting: 7\n";
+        let (location, warnings) = import_location(text, SIZE).unwrap();
+
+        assert_eq!(location.fractal, Fractal::Mandelbrot);
+        assert_eq!(warnings.len(), 5, "{warnings:?}");
+        assert!(warnings[0].contains("more precision"));
+        assert!(warnings[1].starts_with("Formula 5 with power 3"));
+        assert_eq!(
+            warnings[2],
+            "The smooth coloring (Smooth: 1) is not supported"
+        );
+        assert_eq!(
+            warnings[3],
+            "The slope shading (Slopes: 1) is not supported"
+        );
+        assert_eq!(warnings[4], "Unknown settings are ignored: NewSetting");
+    }
+
+    #[test]
+    fn rejects_broken_locations() {
+        assert_eq!(
+            import_location("Im: 0\nZoom: 1", SIZE).unwrap_err(),
+            "The location has no Re"
+        );
+        assert_eq!(
+            import_location("Re: x\nIm: 0\nZoom: 1", SIZE).unwrap_err(),
+            "Invalid Re 'x'"
+        );
+        assert!(import_location("Re: 0\nIm: 0\nZoom: 0", SIZE).is_err());
+        assert_eq!(
+            import_location("Re: 0\nIm: 0\nZoom: 1\nIterations: 0", SIZE).unwrap_err(),
+            "Iterations have to be at least 1"
+        );
+        assert!(import_location("Re: 0\nIm: 0\nZoom: 1\nIterations: -5", SIZE).is_err());
+        assert!(import_location("Re: 0\nIm: 0\nZoom: 1\nRotate: inf", SIZE).is_err());
+        assert!(import_location("Re: 0\nIm: 0\nZoom: 1\nColors: 1,2,", SIZE).is_err());
+    }
+
+    #[test]
+    fn rotations_are_turned_into_one_turn() {
+        for (rotate, degrees) in [
+            ("-90", 270.),
+            ("720.5", 0.5),
+            ("1e20", 1e20_f64.rem_euclid(360.)),
+        ] {
+            let text = format!("Re: 0\nIm: 0\nZoom: 1\nRotate: {rotate}\n");
+            let (location, warnings) = import_location(&text, SIZE).unwrap();
+
+            assert!(
+                (location.rotation - degrees.to_radians()).abs() < 1e-12,
+                "{rotate}"
+            );
+            assert_eq!(warnings.len(), 1, "{rotate}");
+            assert!(warnings[0].starts_with(&format!("Rotate {}", rotate.parse::<f64>().unwrap())));
+        }
+
+        let location = Location {
+            rotation: -30_f64.to_radians(),
+            ..Location::default()
+        };
+        let (text, _) = export_location(&location, SIZE);
+        assert!(text.contains("Rotate: 330"), "{text}");
+    }
+
+    #[test]
+    fn imports_a_complete_location_file() {
+        let text = include_str!("../fixtures/seahorse.kfr");
+        let (location, warnings) = import_location(text, SIZE).unwrap();
+
+        assert_eq!(location.center, (-0.7436438870371587, 0.13182590420531198));
+        assert_eq!(location.iterations, 8200);
+        assert_eq!(location.fractal, Fractal::Mandelbrot);
+        assert_eq!(location.rotation, 0.);
+        assert_eq!(
+            warnings[1..],
+            [
+                "The smooth coloring (Smooth: 1) is not supported",
+                "The slope shading (Slopes: 1) is not supported"
+            ]
+        );
+        assert!(warnings[0].starts_with("Custom palettes are not supported, the 8 colors"));
+    }
+
+    #[test]
+    fn imports_a_complete_palette_file() {
+        let mut location = Location::default();
+        let warnings = import_palette(include_str!("../fixtures/ice.kfp"), &mut location).unwrap();
+
+        assert_eq!(location.palette_offset, 0.25);
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].starts_with("Custom palettes are not supported, the 6 colors"));
+    }
+
+    #[test]
+    fn palettes_map_to_the_closest_one() {
+        let mut location = Location::default();
+        let text = "Colors: 0,255,0,255,0,255,0,255,0,\nColorOffset: 512\n";
+        let warnings = import_palette(text, &mut location).unwrap();
+
+        assert_eq!(location.palette_offset, 0.5);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Custom palettes are not supported, the 3 colors"));
+
+        for palette in Palette::ALL {
+            let (text, _) = export_palette(&Location {
+                palette,
+                ..Location::default()
+            });
+            let mut location = Location::default();
+            assert!(import_palette(&text, &mut location).unwrap().is_empty());
+            assert_eq!(location.palette, palette);
+        }
+    }
+
+    #[test]
+    fn exports_round_trip() {
+        let location = Location {
+            fractal: Fractal::BurningShip,
+            rotation: 45_f64.to_radians(),
+            iterations: 3000,
+            palette: Palette::Fire,
+            palette_offset: 0.25,
+            ..Location::new((-1.7497591451303665, -0.000000003685138), 2.5e7)
+        };
+
+        let (text, warnings) = export_location(&location, SIZE);
+        assert_eq!(warnings.len(), 1);
+        let (imported, warnings) = import_location(&text, SIZE).unwrap();
+        assert!(warnings.is_empty(), "{warnings:?}");
+
+        assert_eq!(imported.center, location.center);
+        assert!((imported.zoom / location.zoom - 1.).abs() < 1e-12);
+        assert!((imported.rotation - location.rotation).abs() < 1e-12);
+        assert_eq!(
+            (
+                imported.fractal,
+                imported.iterations,
+                imported.palette,
+                imported.palette_offset
+            ),
+            (
+                location.fractal,
+                location.iterations,
+                location.palette,
+                location.palette_offset
+            )
+        );
+    }
+
+    #[test]
+    fn warns_about_what_is_not_exported() {
+        let location = Location {
+            radius: 4.,
+            ..Location::default()
+        }
+        .tone_mapper(ToneMapper::new(ToneMap::Aces));
+
+        let (_, warnings) = export_location(&location, SIZE);
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(warnings[0], "Escape radius 4 is not exported");
+        assert_eq!(warnings[2], "Tone mapping is not exported");
+        assert_eq!(export_palette(&location).1.len(), 2);
+    }
+
+    #[test]
+    fn saves_and_loads_files() {
+        let dir = TempDir::new("kf");
+        let path = dir.join("location.kfr");
+        let location = Location::new((-0.5, 0.25), 8.);
+
+        save_location(&path, &location, SIZE).unwrap();
+        let (loaded, _) = load_location(&path, SIZE).unwrap();
+        assert_eq!(loaded.center, location.center);
+
+        let mut colors = Location::default();
+        save_palette(
+            &path,
+            &Location {
+                palette: Palette::Grayscale,
+                ..location
+            },
+        )
+        .unwrap();
+        load_palette(&path, &mut colors).unwrap();
+        assert_eq!(colors.palette, Palette::Grayscale);
+
+        fs::write(&path, "Re: 0").unwrap();
+        assert_eq!(
+            load_location(&path, SIZE).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -1,5 +1,29 @@
+pub mod animation;
+pub mod antialias;
+mod auto_iterations;
+mod cancel;
 pub mod color;
 mod data;
+pub mod dither;
+pub mod exp_map;
+pub mod hdr;
+pub mod kf;
+pub mod location;
+pub mod palette_loop;
+pub mod parallel_png;
+mod progressive;
+mod quantize;
+pub mod raw;
 mod sets;
+mod subdivision;
+#[cfg(test)]
+mod test_util;
+pub mod tiles;
+pub mod y4m;
+pub mod zoom_sequence;
 
-pub use sets::{Dim, Mandelbrot};
+pub use auto_iterations::{AutoIterations, EscapeStats};
+pub use cancel::CancelToken;
+pub use data::{png_crate, ppm, ColorMap};
+pub use progressive::Progressive;
+pub use sets::{Dim, Fractal, Mandelbrot, Strategy, Strips};
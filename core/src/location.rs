@@ -0,0 +1,677 @@
+//!Everything needed to render a view again, stored in the text chunks of exported png images
+//!or in location files.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::animation::{Keyframe, DEFAULT_WIDTH};
+use crate::color::Palette;
+use crate::dither::Dither;
+use crate::hdr::{ToneMap, ToneMapper};
+use crate::{Dim, Fractal, Mandelbrot};
+
+///View and coloring of a render, independent of the image size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    pub fractal: Fractal,
+    pub center: (f64, f64),
+    ///Size of the default view divided by the size of this one
+    pub zoom: f64,
+    ///Counterclockwise rotation in radians
+    pub rotation: f64,
+    pub iterations: u64,
+    pub radius: f64,
+    pub palette: Palette,
+    ///Shifts the colors along the palette, from 0 to 1
+    pub palette_offset: f32,
+    ///Conversion of linear colors into 8 bit ones
+    pub tone_mapper: ToneMapper,
+}
+
+impl Default for Location {
+    fn default() -> Self {
+        Self::new((-0.765, 0.), 1.)
+    }
+}
+
+impl Location {
+    pub fn new(center: (f64, f64), zoom: f64) -> Self {
+        Self {
+            fractal: Fractal::Mandelbrot,
+            center,
+            zoom,
+            rotation: 0.,
+            iterations: 1_000,
+            radius: 2.,
+            palette: Palette::Rainbow,
+            palette_offset: 0.,
+            tone_mapper: ToneMapper::default(),
+        }
+    }
+
+    pub fn tone_mapper(mut self, tone_mapper: ToneMapper) -> Self {
+        self.tone_mapper = tone_mapper;
+        self
+    }
+
+    ///Location of the view of a set, colors are not tone mapped
+    pub fn of(set: &Mandelbrot) -> Self {
+        let (x_range, y_range) = (set.x_range, set.y_range);
+
+        Self {
+            fractal: set.fractal,
+            center: ((x_range.0 + x_range.1) / 2., (y_range.0 + y_range.1) / 2.),
+            zoom: DEFAULT_WIDTH / (x_range.1 - x_range.0),
+            rotation: set.rotation,
+            iterations: set.max_iterations,
+            radius: set.radius,
+            palette: set.palette,
+            palette_offset: set.palette_offset,
+            tone_mapper: ToneMapper::default(),
+        }
+    }
+
+    ///View of the location as an animation keyframe
+    pub fn keyframe(&self) -> Keyframe {
+        Keyframe::new(self.center, self.zoom)
+            .rotation(self.rotation)
+            .iterations(self.iterations)
+            .palette_offset(self.palette_offset)
+    }
+
+    ///Real and imaginary range of the view for an image size
+    pub fn ranges(&self, size: Dim<usize>) -> ((f64, f64), (f64, f64)) {
+        self.keyframe().ranges(size)
+    }
+
+    ///Render settings of the location for an image size
+    pub fn set(&self, size: Dim<usize>) -> Mandelbrot {
+        let (x_range, y_range) = self.ranges(size);
+
+        Mandelbrot::from_range(size, x_range, y_range)
+            .fractal(self.fractal)
+            .rotation(self.rotation)
+            .max_iterations(self.iterations)
+            .radius(self.radius)
+            .palette(self.palette)
+            .palette_offset(self.palette_offset)
+    }
+
+    ///Keywords and values of the png text chunks, numbers keep their full precision
+    pub fn text_chunks(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Software", "mandelbrot".to_string()),
+            ("Fractal", self.fractal.name().to_string()),
+            ("Center real", self.center.0.to_string()),
+            ("Center imaginary", self.center.1.to_string()),
+            ("Zoom", self.zoom.to_string()),
+            ("Rotation", self.rotation.to_string()),
+            ("Iterations", self.iterations.to_string()),
+            ("Escape radius", self.radius.to_string()),
+            ("Palette", self.palette.name().to_string()),
+            ("Palette offset", self.palette_offset.to_string()),
+            ("Tone map", self.tone_mapper.tone_map.name().to_string()),
+            ("Exposure", self.tone_mapper.exposure.to_string()),
+            ("Dither", self.tone_mapper.dither.name().to_string()),
+        ]
+    }
+
+    ///Read a location back from text chunks, only fractal, center and zoom are required
+    pub fn from_text_chunks<'a>(
+        chunks: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, String> {
+        let chunks: Vec<_> = chunks.into_iter().collect();
+        let text = |keyword: &str| {
+            chunks
+                .iter()
+                .find(|(k, _)| *k == keyword)
+                .map(|(_, text)| text.trim())
+        };
+        fn number<T: std::str::FromStr>(
+            text: Option<&str>,
+            name: &str,
+        ) -> Result<Option<T>, String> {
+            text.map(|text| text.parse().map_err(|_| format!("Invalid {name} '{text}'")))
+                .transpose()
+        }
+        let missing = |name: &str| format!("The image has no {name}");
+
+        let fractal = text("Fractal").ok_or_else(|| missing("fractal"))?;
+        let fractal =
+            Fractal::from_name(fractal).ok_or_else(|| format!("Unknown fractal '{fractal}'"))?;
+
+        let real = number(text("Center real"), "center")?.ok_or_else(|| missing("center"))?;
+        let imaginary =
+            number(text("Center imaginary"), "center")?.ok_or_else(|| missing("center"))?;
+        let zoom = number(text("Zoom"), "zoom")?.ok_or_else(|| missing("zoom"))?;
+
+        let mut location = Self::new((real, imaginary), zoom);
+        location.fractal = fractal;
+        if let Some(rotation) = number(text("Rotation"), "rotation")? {
+            location.rotation = rotation;
+        }
+        if let Some(iterations) = number(text("Iterations"), "iterations")? {
+            location.iterations = iterations;
+        }
+        if let Some(radius) = number(text("Escape radius"), "escape radius")? {
+            location.radius = radius;
+        }
+        if let Some(palette) = text("Palette") {
+            location.palette = Palette::from_name(palette)
+                .ok_or_else(|| format!("Unknown palette '{palette}'"))?;
+        }
+        if let Some(offset) = number(text("Palette offset"), "palette offset")? {
+            location.palette_offset = offset;
+        }
+        if let Some(tone_map) = text("Tone map") {
+            location.tone_mapper.tone_map = ToneMap::from_name(tone_map)
+                .ok_or_else(|| format!("Unknown tone map '{tone_map}'"))?;
+        }
+        if let Some(exposure) = number(text("Exposure"), "exposure")? {
+            location.tone_mapper.exposure = exposure;
+        }
+        if let Some(dither) = text("Dither") {
+            location.tone_mapper.dither =
+                Dither::from_name(dither).ok_or_else(|| format!("Unknown dithering '{dither}'"))?;
+        }
+
+        Ok(location)
+    }
+
+    ///Add the location as iTXt chunks before the image data
+    pub fn add_to_png<W: Write>(&self, encoder: &mut png::Encoder<W>) -> io::Result<()> {
+        for (keyword, text) in self.text_chunks() {
+            encoder.add_itxt_chunk(keyword.to_string(), text)?;
+        }
+        Ok(())
+    }
+
+    ///Location stored in the text chunks of a png and the size of the image
+    pub fn from_png(path: &Path) -> io::Result<(Self, Dim<usize>)> {
+        let decoder = png::Decoder::new(File::open(path)?);
+        let reader = decoder.read_info()?;
+        let info = reader.info();
+        let size = Dim::new(info.width as usize, info.height as usize);
+
+        //Text chunks of any kind, iTXt is what gets written
+        let mut chunks: Vec<(String, String)> = Vec::new();
+        for chunk in &info.utf8_text {
+            let text = chunk
+                .get_text()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            chunks.push((chunk.keyword.clone(), text));
+        }
+        for chunk in &info.uncompressed_latin1_text {
+            chunks.push((chunk.keyword.clone(), chunk.text.clone()));
+        }
+
+        let location = Self::from_text_chunks(chunks.iter().map(|(k, t)| (k.as_str(), t.as_str())))
+            .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))?;
+        Ok((location, size))
+    }
+
+    ///Location from a png we rendered or a location file, by the extension.
+    ///
+    ///Images always have a size, location files only if one was saved.
+    pub fn open(path: &Path) -> io::Result<(Self, Option<Dim<usize>>)> {
+        let png = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+
+        if png {
+            let (location, size) = Self::from_png(path)?;
+            Ok((location, Some(size)))
+        } else {
+            file::load(path)
+        }
+    }
+}
+
+///Location files with the whole render configuration, as TOML or JSON.
+///
+///```toml
+///version = 1
+///size = [1920, 1080]
+///
+///[view]
+///fractal = "mandelbrot"
+///center = [-0.743643887037151, 0.13182590420533]
+///zoom = 3000.0
+///rotation = 0.0
+///iterations = 2500
+///escape_radius = 2.0
+///
+///[color]
+///palette = "fire"
+///offset = 0.0
+///tone_map = "clamp"
+///exposure = 0.0
+///dither = "none"
+///```
+///
+///Everything except the version is optional. Older versions are upgraded when they are read,
+///newer ones are rejected.
+pub mod file {
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use std::fs;
+
+    use super::*;
+
+    ///Version of the files that get written
+    pub const VERSION: u64 = 1;
+
+    ///Upgrades of older versions, the first one turns version 1 into version 2
+    const MIGRATIONS: &[fn(&mut Value)] = &[];
+
+    const _: () = assert!(MIGRATIONS.len() as u64 + 1 == VERSION);
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Format {
+        Toml,
+        Json,
+    }
+
+    impl Format {
+        ///Format of a file, TOML unless the extension is `json`
+        pub fn from_path(path: &Path) -> Self {
+            let json = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+            if json {
+                Format::Json
+            } else {
+                Format::Toml
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct File {
+        version: u64,
+        ///Width and height of the image in pixels
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<[usize; 2]>,
+        #[serde(default)]
+        view: View,
+        #[serde(default)]
+        color: Color,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct View {
+        fractal: Fractal,
+        ///Real and imaginary part
+        center: [f64; 2],
+        zoom: f64,
+        ///Counterclockwise rotation in degrees
+        rotation: f64,
+        iterations: u64,
+        escape_radius: f64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Color {
+        palette: Palette,
+        offset: f32,
+        tone_map: ToneMap,
+        exposure: f32,
+        dither: Dither,
+    }
+
+    impl Default for View {
+        fn default() -> Self {
+            File::new(&Location::default(), None).view
+        }
+    }
+
+    impl Default for Color {
+        fn default() -> Self {
+            File::new(&Location::default(), None).color
+        }
+    }
+
+    impl File {
+        fn new(location: &Location, size: Option<Dim<usize>>) -> Self {
+            Self {
+                version: VERSION,
+                size: size.map(|size| [size.x, size.y]),
+                view: View {
+                    fractal: location.fractal,
+                    center: [location.center.0, location.center.1],
+                    zoom: location.zoom,
+                    rotation: location.rotation.to_degrees(),
+                    iterations: location.iterations,
+                    escape_radius: location.radius,
+                },
+                color: Color {
+                    palette: location.palette,
+                    offset: location.palette_offset,
+                    tone_map: location.tone_mapper.tone_map,
+                    exposure: location.tone_mapper.exposure,
+                    dither: location.tone_mapper.dither,
+                },
+            }
+        }
+
+        fn location(&self) -> Location {
+            let (view, color) = (&self.view, &self.color);
+
+            Location {
+                fractal: view.fractal,
+                center: (view.center[0], view.center[1]),
+                zoom: view.zoom,
+                rotation: view.rotation.to_radians(),
+                iterations: view.iterations,
+                radius: view.escape_radius,
+                palette: color.palette,
+                palette_offset: color.offset,
+                tone_mapper: ToneMapper::new(color.tone_map)
+                    .exposure(color.exposure)
+                    .dither(color.dither),
+            }
+        }
+    }
+
+    ///Bring a file of any older version up to the current one
+    fn migrate(value: Value) -> Result<Value, String> {
+        upgrade(value, MIGRATIONS)
+    }
+
+    ///Apply the migrations from the version of a file on, the newest version is one more than there are migrations
+    fn upgrade(mut value: Value, migrations: &[fn(&mut Value)]) -> Result<Value, String> {
+        let newest = migrations.len() as u64 + 1;
+        let version = value
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or("The file has no version")?;
+        if version == 0 || version > newest {
+            return Err(format!(
+                "Unsupported version {version}, the newest known version is {newest}"
+            ));
+        }
+
+        for migration in &migrations[version as usize - 1..] {
+            migration(&mut value);
+        }
+        value["version"] = newest.into();
+        Ok(value)
+    }
+
+    ///Text of a location file, the image size is optional
+    pub fn to_string(
+        location: &Location,
+        size: Option<Dim<usize>>,
+        format: Format,
+    ) -> Result<String, String> {
+        let file = File::new(location, size);
+
+        match format {
+            Format::Toml => toml::to_string(&file)
+                .map(|text| format!("# Mandelbrot location\n{text}"))
+                .map_err(|err| err.to_string()),
+            Format::Json => serde_json::to_string_pretty(&file)
+                .map(|text| text + "\n")
+                .map_err(|err| err.to_string()),
+        }
+    }
+
+    ///Read the text of a location file of any supported version
+    pub fn parse(text: &str, format: Format) -> Result<(Location, Option<Dim<usize>>), String> {
+        let value: Value = match format {
+            Format::Toml => toml::from_str(text).map_err(|err| err.to_string())?,
+            Format::Json => serde_json::from_str(text).map_err(|err| err.to_string())?,
+        };
+        let file: File = serde_json::from_value(migrate(value)?).map_err(|err| err.to_string())?;
+
+        let size = file.size.map(|[x, y]| Dim::new(x, y));
+        if size.is_some_and(|size| size.x == 0 || size.y == 0) {
+            return Err("Image size can't be zero".to_string());
+        }
+        Ok((file.location(), size))
+    }
+
+    pub fn save(path: &Path, location: &Location, size: Option<Dim<usize>>) -> io::Result<()> {
+        let text = to_string(location, size, Format::from_path(path)).map_err(io::Error::other)?;
+        fs::write(path, text)
+    }
+
+    pub fn load(path: &Path) -> io::Result<(Location, Option<Dim<usize>>)> {
+        let text = fs::read_to_string(path)?;
+        parse(&text, Format::from_path(path))
+            .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_util::TempDir;
+        use serde_json::json;
+
+        fn example() -> Location {
+            Location {
+                fractal: Fractal::BurningShip,
+                center: (-1.7497591451303665, -0.0000000036851380),
+                zoom: 123456.789,
+                rotation: 30_f64.to_radians(),
+                iterations: 4321,
+                radius: 4.,
+                palette: Palette::Fire,
+                palette_offset: 0.25,
+                tone_mapper: ToneMapper::new(ToneMap::Aces)
+                    .exposure(-1.5)
+                    .dither(Dither::BlueNoise),
+            }
+        }
+
+        #[test]
+        fn round_trips() {
+            let size = Some(Dim::new(640, 480));
+            for format in [Format::Toml, Format::Json] {
+                let text = to_string(&example(), size, format).unwrap();
+                let (location, parsed_size) = parse(&text, format).unwrap();
+
+                assert!((location.rotation - example().rotation).abs() < 1e-15);
+                assert_eq!(
+                    Location {
+                        rotation: example().rotation,
+                        ..location
+                    },
+                    example()
+                );
+                assert_eq!(parsed_size, size);
+                assert_eq!(to_string(&location, parsed_size, format).unwrap(), text);
+            }
+        }
+
+        #[test]
+        fn formats_from_the_extension() {
+            assert_eq!(Format::from_path(Path::new("a.JSON")), Format::Json);
+            assert_eq!(Format::from_path(Path::new("a.toml")), Format::Toml);
+            assert_eq!(Format::from_path(Path::new("a")), Format::Toml);
+        }
+
+        #[test]
+        fn everything_but_the_version_is_optional() {
+            let (location, size) = parse("version = 1", Format::Toml).unwrap();
+            assert_eq!(location, Location::default());
+            assert_eq!(size, None);
+
+            let (location, _) =
+                parse(r#"{"version": 1, "view": {"zoom": 8}}"#, Format::Json).unwrap();
+            assert_eq!(location.zoom, 8.);
+        }
+
+        #[test]
+        fn rejects_unsupported_versions() {
+            let newer = format!("version = {}", VERSION + 1);
+            for text in [newer.as_str(), "version = 0"] {
+                let err = parse(text, Format::Toml).unwrap_err();
+                assert!(err.starts_with("Unsupported version"), "{err}");
+            }
+            assert_eq!(
+                parse("[view]", Format::Toml).unwrap_err(),
+                "The file has no version"
+            );
+        }
+
+        #[test]
+        fn rejects_broken_files() {
+            for text in [
+                "version = 1\nzoom = 2",
+                "version = 1\n[view]\nfractal = \"julia\"",
+                "version = 1\nsize = [0, 10]",
+                "version = ",
+            ] {
+                assert!(parse(text, Format::Toml).is_err(), "{text}");
+            }
+        }
+
+        #[test]
+        fn migrates_from_every_older_version() {
+            //Version 2 renamed the radius, version 3 moved the size into the view
+            fn rename_radius(value: &mut Value) {
+                let view = value["view"].as_object_mut().unwrap();
+                let radius = view.remove("radius").unwrap();
+                view.insert("escape_radius".to_string(), radius);
+            }
+            fn move_size(value: &mut Value) {
+                let size = value.as_object_mut().unwrap().remove("size").unwrap();
+                value["view"]["size"] = size;
+            }
+            let migrations: &[fn(&mut Value)] = &[rename_radius, move_size];
+
+            let first = json!({"version": 1, "size": [4, 3], "view": {"radius": 8}});
+            assert_eq!(
+                upgrade(first, migrations).unwrap(),
+                json!({"version": 3, "view": {"escape_radius": 8, "size": [4, 3]}})
+            );
+
+            let second = json!({"version": 2, "size": [4, 3], "view": {"radius": 8}});
+            assert_eq!(
+                upgrade(second, migrations).unwrap(),
+                json!({"version": 3, "view": {"radius": 8, "size": [4, 3]}})
+            );
+
+            let current = json!({"version": 3, "view": {}});
+            assert_eq!(upgrade(current.clone(), migrations).unwrap(), current);
+            assert!(upgrade(json!({"version": 4}), migrations).is_err());
+        }
+
+        #[test]
+        fn saves_and_loads_files() {
+            let dir = TempDir::new("location-file");
+            for name in ["location.toml", "location.json"] {
+                let path = dir.join(name);
+                save(&path, &example(), None).unwrap();
+                let (location, size) = load(&path).unwrap();
+
+                assert_eq!(location.center, example().center);
+                assert_eq!(size, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::{parallel_png, png_crate, ColorMap};
+
+    fn example() -> Location {
+        Location {
+            fractal: Fractal::BurningShip,
+            rotation: 0.1,
+            iterations: 777,
+            radius: 3.5,
+            palette: Palette::Grayscale,
+            palette_offset: 0.3,
+            ..Location::new((-1.7497591451303665, -0.0000000036851380), 1.5e9)
+        }
+        .tone_mapper(
+            ToneMapper::new(ToneMap::Reinhard)
+                .exposure(0.5)
+                .dither(Dither::FloydSteinberg),
+        )
+    }
+
+    #[test]
+    fn text_chunks_keep_full_precision() {
+        let chunks = example().text_chunks();
+        let parsed = Location::from_text_chunks(chunks.iter().map(|(k, t)| (*k, t.as_str())));
+        assert_eq!(parsed.unwrap(), example());
+    }
+
+    #[test]
+    fn only_the_view_is_required() {
+        let minimal = [
+            ("Fractal", "mandelbrot"),
+            ("Center real", "-0.5"),
+            ("Center imaginary", " 0.25 "),
+            ("Zoom", "4"),
+        ];
+        assert_eq!(
+            Location::from_text_chunks(minimal).unwrap(),
+            Location::new((-0.5, 0.25), 4.)
+        );
+
+        assert_eq!(
+            Location::from_text_chunks(minimal[..3].iter().copied())
+                .err()
+                .unwrap(),
+            "The image has no zoom"
+        );
+        assert_eq!(
+            Location::from_text_chunks([]).err().unwrap(),
+            "The image has no fractal"
+        );
+
+        let mut broken = minimal.to_vec();
+        broken.push(("Iterations", "-1"));
+        assert_eq!(
+            Location::from_text_chunks(broken).err().unwrap(),
+            "Invalid iterations '-1'"
+        );
+    }
+
+    #[test]
+    fn sets_keep_the_view() {
+        let location = example();
+        let set = location.set(Dim::new(300, 200));
+        assert_eq!(set.image_size, Dim::new(300, 200));
+
+        let back = Location::of(&set);
+        assert!((back.center.0 - location.center.0).abs() < 1e-15);
+        assert!((back.center.1 - location.center.1).abs() < 1e-15);
+        //The width of the range only has a few significant digits left this deep
+        assert!((back.zoom / location.zoom - 1.).abs() < 1e-6);
+        assert_eq!(
+            (back.fractal, back.iterations, back.palette),
+            (location.fractal, location.iterations, location.palette)
+        );
+    }
+
+    #[test]
+    fn images_open_as_locations() {
+        let dir = TempDir::new("location");
+        let path = dir.join("location.png");
+        let map = ColorMap::new(6, 4);
+
+        parallel_png::save(&path, &map, Some(&example())).unwrap();
+        let (location, size) = Location::from_png(&path).unwrap();
+        assert_eq!(location, example());
+        assert_eq!(size, Dim::new(6, 4));
+
+        //Images from elsewhere have no location
+        png_crate::save(&path, &map).unwrap();
+        assert_eq!(
+            Location::from_png(&path).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
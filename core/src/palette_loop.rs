@@ -0,0 +1,182 @@
+//!Looping animations that cycle the colors through the palette, saved as animated png or gif.
+//!
+//!The fractal is calculated once, every frame only colors the same iteration counts
+//!with a larger palette offset. After the last frame the palette went around exactly once.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::location::Location;
+use crate::quantize::{self, Histogram};
+use crate::{ColorMap, Mandelbrot};
+
+///Largest palette of a gif
+const GIF_COLORS: usize = 256;
+
+///Palette cycling loop of a single view
+#[derive(Clone)]
+pub struct PaletteLoop {
+    ///View, colors and palette offset of the first frame
+    pub set: Mandelbrot,
+    pub frames: usize,
+    pub fps: u16,
+}
+
+impl PaletteLoop {
+    pub fn new(set: Mandelbrot, frames: usize) -> Self {
+        Self {
+            set,
+            frames,
+            fps: 25,
+        }
+    }
+
+    pub fn fps(mut self, fps: u16) -> Self {
+        self.fps = fps.max(1);
+        self
+    }
+
+    ///Colors of a frame from the iteration counts of the view
+    pub fn frame(&self, iterations: &ColorMap<u64>, frame: usize) -> ColorMap<[u8; 3]> {
+        let offset = frame as f32 / self.frames.max(1) as f32;
+        let set = self
+            .set
+            .clone()
+            .palette_offset(self.set.palette_offset + offset);
+
+        iterations.map(|&iter| set.color(iter))
+    }
+
+    ///Save the loop as animated png that repeats forever, the first frame keeps the location
+    pub fn save_apng(&self, path: &Path) -> io::Result<()> {
+        let iterations = self.set.iteration_map();
+        let (width, height) = (iterations.width as u32, iterations.height as u32);
+
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.max(1) as u32, 0)?;
+        encoder.set_frame_delay(1, self.fps)?;
+        Location::of(&self.set).add_to_png(&mut encoder)?;
+
+        let mut writer = encoder.write_header()?;
+        for frame in 0..self.frames.max(1) {
+            let colors = self.frame(&iterations, frame);
+            writer.write_image_data(colors.data.concat().as_slice())?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    ///Save the loop as gif that repeats forever.
+    ///
+    ///All frames share one palette from a median cut over the colors of every frame,
+    ///which keeps the colors from flickering between frames.
+    pub fn save_gif(&self, path: &Path) -> io::Result<()> {
+        let iterations = self.set.iteration_map();
+        let too_large = |_| io::Error::new(io::ErrorKind::InvalidInput, "Too large for a gif");
+        let width = u16::try_from(iterations.width).map_err(too_large)?;
+        let height = u16::try_from(iterations.height).map_err(too_large)?;
+        let frames = self.frames.max(1);
+
+        let mut histogram = Histogram::new();
+        for frame in 0..frames {
+            for &color in &self.frame(&iterations, frame).data {
+                *histogram.entry(color).or_default() += 1;
+            }
+        }
+        let palette = quantize::median_cut(&histogram, GIF_COLORS);
+        let lookup = quantize::lookup(&palette, &histogram);
+
+        let gif_error = |err: gif::EncodingError| io::Error::other(err);
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder =
+            gif::Encoder::new(file, width, height, &palette.concat()).map_err(gif_error)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(gif_error)?;
+
+        //Gif delays are in hundredths of a second
+        let delay = (100. / self.fps as f64).round().max(1.) as u16;
+        for frame in 0..frames {
+            let colors = self.frame(&iterations, frame);
+            let indices: Vec<u8> = colors.data.iter().map(|color| lookup[color]).collect();
+
+            let mut frame = gif::Frame::from_indexed_pixels(width, height, &indices, None);
+            frame.delay = delay;
+            encoder.write_frame(&frame).map_err(gif_error)?;
+        }
+        encoder.into_inner()?.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Palette;
+    use crate::test_util::TempDir;
+    use crate::Dim;
+
+    fn palette_loop() -> PaletteLoop {
+        let set = Mandelbrot::from_range(Dim::new(24, 16), (-2., 0.5), (-1., 1.))
+            .max_iterations(100)
+            .palette(Palette::Fire);
+        PaletteLoop::new(set, 6).fps(12)
+    }
+
+    #[test]
+    fn frames_cycle_the_palette() {
+        let palette_loop = palette_loop();
+        let set = &palette_loop.set;
+        let iterations = set.iteration_map();
+
+        let first = palette_loop.frame(&iterations, 0);
+        assert_eq!(first.data, iterations.map(|&iter| set.color(iter)).data);
+
+        let half = set.clone().palette_offset(0.5);
+        assert_eq!(
+            palette_loop.frame(&iterations, 3).data,
+            iterations.map(|&iter| half.color(iter)).data
+        );
+        assert_ne!(palette_loop.frame(&iterations, 1).data, first.data);
+    }
+
+    #[test]
+    fn apng_has_every_frame() {
+        let dir = TempDir::new("palette-loop");
+        let path = dir.join("loop.png");
+        palette_loop().save_apng(&path).unwrap();
+
+        let reader = png::Decoder::new(File::open(&path).unwrap())
+            .read_info()
+            .unwrap();
+        let control = reader.info().animation_control.unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (6, 0));
+
+        let (location, size) = Location::from_png(&path).unwrap();
+        assert_eq!(location, Location::of(&palette_loop().set));
+        assert_eq!((size.x, size.y), (24, 16));
+    }
+
+    #[test]
+    fn gif_frames_share_one_palette() {
+        let dir = TempDir::new("palette-loop");
+        let path = dir.join("loop.gif");
+        let palette_loop = palette_loop();
+        palette_loop.save_gif(&path).unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        assert!(decoder.global_palette().unwrap().len() <= GIF_COLORS * 3);
+
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!((frame.width, frame.height, frame.delay), (24, 16, 8));
+            frames += 1;
+        }
+        assert_eq!(frames, 6);
+    }
+}
@@ -0,0 +1,369 @@
+//!PNG writer that compresses independent chunks of rows on multiple threads.
+//!
+//!Every chunk is filtered and deflated on its own and ends on a byte boundary,
+//!so the pieces can be stitched together into a single zlib stream.
+
+use miniz_oxide::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::location::Location;
+use crate::ColorMap;
+
+///Uncompressed bytes per chunk, every chunk has at least one row
+const CHUNK_SIZE: usize = 1 << 17;
+///Bytes per pixel
+const BPP: usize = 3;
+const COMPRESSION_LEVEL: i32 = 6;
+
+const ADLER_BASE: u32 = 65521;
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+
+    //Largest block that can't overflow before taking the modulo
+    for block in data.chunks(5552) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= ADLER_BASE;
+        b %= ADLER_BASE;
+    }
+
+    (b << 16) | a
+}
+
+///Checksum of two concatenated pieces of data, like zlib's `adler32_combine`
+fn adler32_combine(first: u32, second: u32, second_len: usize) -> u32 {
+    let rem = (second_len % ADLER_BASE as usize) as u32;
+
+    let mut a = first & 0xffff;
+    let mut b = (rem * a) % ADLER_BASE;
+    a += (second & 0xffff) + ADLER_BASE - 1;
+    b += (first >> 16) + (second >> 16) + ADLER_BASE - rem;
+
+    if a >= ADLER_BASE {
+        a -= ADLER_BASE;
+    }
+    if a >= ADLER_BASE {
+        a -= ADLER_BASE;
+    }
+    if b >= 2 * ADLER_BASE {
+        b -= 2 * ADLER_BASE;
+    }
+    if b >= ADLER_BASE {
+        b -= ADLER_BASE;
+    }
+
+    (b << 16) | a
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+///Filter a row with every PNG filter type and keep the one with the smallest sum of absolute values
+fn filter_row(row: &[u8], previous: &[u8], out: &mut Vec<u8>) {
+    let mut candidate = vec![0; row.len()];
+    let mut best = (u64::MAX, 0, Vec::new());
+
+    for filter in 0..5_u8 {
+        //The first pixel has no left neighbor
+        for i in 0..BPP.min(row.len()) {
+            let predicted = match filter {
+                2 | 4 => previous[i],
+                3 => previous[i] / 2,
+                _ => 0,
+            };
+            candidate[i] = row[i].wrapping_sub(predicted);
+        }
+
+        let rest = BPP.min(row.len())..row.len();
+        match filter {
+            0 => candidate[rest.clone()].copy_from_slice(&row[rest]),
+            1 => rest.for_each(|i| candidate[i] = row[i].wrapping_sub(row[i - BPP])),
+            2 => rest.for_each(|i| candidate[i] = row[i].wrapping_sub(previous[i])),
+            3 => rest.for_each(|i| {
+                let average = (row[i - BPP] as u16 + previous[i] as u16) / 2;
+                candidate[i] = row[i].wrapping_sub(average as u8);
+            }),
+            _ => rest.for_each(|i| {
+                let predicted = paeth(row[i - BPP], previous[i], previous[i - BPP]);
+                candidate[i] = row[i].wrapping_sub(predicted);
+            }),
+        }
+
+        let sum = candidate
+            .iter()
+            .map(|&v| (v as i8).unsigned_abs() as u64)
+            .sum();
+        if sum < best.0 {
+            best = (sum, filter, candidate.clone());
+        }
+    }
+
+    out.push(best.1);
+    out.extend_from_slice(&best.2);
+}
+
+///Raw deflate data of one chunk, the last chunk of the image also ends the stream
+fn compress(data: &[u8], last: bool) -> Vec<u8> {
+    let flags = create_comp_flags_from_zip_params(COMPRESSION_LEVEL, -15, 0);
+    let mut compressor = CompressorOxide::new(flags);
+    let flush = if last {
+        TDEFLFlush::Finish
+    } else {
+        TDEFLFlush::Sync
+    };
+
+    let mut out = Vec::with_capacity(data.len() / 2);
+    let (status, _) = compress_to_output(&mut compressor, data, flush, |bytes| {
+        out.extend_from_slice(bytes);
+        true
+    });
+    debug_assert!(matches!(status, TDEFLStatus::Okay | TDEFLStatus::Done));
+
+    out
+}
+
+///Writes an RGB png, the rows can be passed in strips of any height
+pub struct ParallelPng<W: Write> {
+    writer: png::Writer<W>,
+    width: usize,
+    rows_left: usize,
+    ///Last row of the previous strip, the filters need it
+    previous_row: Vec<u8>,
+    ///Checksum of all filtered data so far
+    adler: u32,
+}
+
+impl<W: Write> ParallelPng<W> {
+    ///Write the header of an image, takes an encoder so more chunks can be added to it.
+    ///
+    ///Png has no images without pixels, so a width or height of zero is an error.
+    pub fn new(mut encoder: png::Encoder<'_, W>, width: usize, height: usize) -> io::Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("a png can't be {width}x{height} pixels"),
+            ));
+        }
+
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        //zlib header, deflate with a 32K window and the default level
+        writer.write_chunk(png::chunk::IDAT, &[0x78, 0x9c])?;
+
+        Ok(Self {
+            writer,
+            width,
+            rows_left: height,
+            previous_row: vec![0; width * BPP],
+            adler: 1,
+        })
+    }
+
+    ///Filter and compress the next rows, the chunks are compressed in parallel
+    pub fn write_rows(&mut self, rows: &ColorMap<[u8; 3]>) -> io::Result<()> {
+        let row_len = self.width * BPP;
+        let raw: Vec<u8> = rows.data.concat();
+        if rows.width != self.width || rows.height > self.rows_left {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "rows don't fit into the image",
+            ));
+        }
+
+        self.rows_left -= rows.height;
+        let last_strip = self.rows_left == 0;
+
+        let chunk_rows = (CHUNK_SIZE / row_len.max(1)).max(1);
+        let chunks = rows.height.div_ceil(chunk_rows);
+        let previous_row = &self.previous_row;
+
+        let compressed: Vec<(Vec<u8>, u32, usize)> = (0..chunks)
+            .into_par_iter()
+            .map(|chunk| {
+                let start = chunk * chunk_rows;
+                let end = (start + chunk_rows).min(rows.height);
+
+                let mut filtered = Vec::with_capacity((end - start) * (row_len + 1));
+                for y in start..end {
+                    let row = &raw[y * row_len..(y + 1) * row_len];
+                    let previous = match y {
+                        0 => &previous_row[..],
+                        _ => &raw[(y - 1) * row_len..y * row_len],
+                    };
+                    filter_row(row, previous, &mut filtered);
+                }
+
+                let last = last_strip && chunk == chunks - 1;
+                (
+                    compress(&filtered, last),
+                    adler32(&filtered),
+                    filtered.len(),
+                )
+            })
+            .collect();
+
+        for (data, adler, len) in compressed {
+            self.adler = adler32_combine(self.adler, adler, len);
+            self.writer.write_chunk(png::chunk::IDAT, &data)?;
+        }
+
+        if let Some(row) = raw.chunks(row_len.max(1)).last() {
+            self.previous_row = row.to_vec();
+        }
+
+        Ok(())
+    }
+
+    ///Write the checksum and the end of the image
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.rows_left > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} rows are missing", self.rows_left),
+            ));
+        }
+
+        self.writer
+            .write_chunk(png::chunk::IDAT, &self.adler.to_be_bytes())?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+///Write a png strip by strip with parallel compression, works like `png_crate::save_strips`.
+///
+///A location gets stored in the text chunks, so the image can be opened as a view again.
+pub fn save_strips(
+    path: &Path,
+    width: usize,
+    height: usize,
+    strip_height: usize,
+    location: Option<&Location>,
+    mut strip: impl FnMut(usize, usize) -> ColorMap<[u8; 3]>,
+) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    if let Some(location) = location {
+        location.add_to_png(&mut encoder)?;
+    }
+
+    let mut png = ParallelPng::new(encoder, width, height)?;
+    for y in (0..height).step_by(strip_height.max(1)) {
+        let rows = strip_height.min(height - y);
+        png.write_rows(&strip(y, rows))?;
+    }
+    png.finish()
+}
+
+///Save a color map as png with parallel compression and an optional location
+pub fn save(path: &Path, map: &ColorMap<[u8; 3]>, location: Option<&Location>) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), map.width as u32, map.height as u32);
+    if let Some(location) = location {
+        location.add_to_png(&mut encoder)?;
+    }
+
+    let mut png = ParallelPng::new(encoder, map.width, map.height)?;
+    png.write_rows(map)?;
+    png.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::png_crate;
+    use crate::test_util::TempDir;
+
+    fn noise(width: usize, height: usize) -> ColorMap<[u8; 3]> {
+        let mut map = ColorMap::new(width, height);
+        for (i, color) in map.data.iter_mut().enumerate() {
+            let z = (i as u64).wrapping_mul(0x9e3779b97f4a7c15) >> 40;
+            //Smooth areas and noise, so every filter gets picked somewhere
+            *color = match i % 3 {
+                0 => [(i % 256) as u8, (i / 7 % 256) as u8, 128],
+                _ => [z as u8, (z >> 8) as u8, (z >> 16) as u8],
+            };
+        }
+        map
+    }
+
+    #[test]
+    fn adler32_matches_zlib() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+
+        let data: Vec<u8> = (0..20_000).map(|i| (i * 7 % 251) as u8).collect();
+        for split in [0, 1, 5552, 12_345, data.len()] {
+            let (first, second) = data.split_at(split);
+            assert_eq!(
+                adler32_combine(adler32(first), adler32(second), second.len()),
+                adler32(&data)
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_like_the_original() {
+        let dir = TempDir::new("parallel-png");
+        //Multiple chunks per strip and a last strip with a single row
+        let map = noise(300, 301);
+        let path = dir.join("strips.png");
+
+        save_strips(&path, 300, 301, 150, None, |y, rows| {
+            map.crop(0, y, 300, rows)
+        })
+        .unwrap();
+        assert_eq!(png_crate::load(&path).unwrap().data, map.data);
+
+        save(&path, &noise(1, 1), None).unwrap();
+        assert_eq!(png_crate::load(&path).unwrap().data, noise(1, 1).data);
+    }
+
+    #[test]
+    fn rows_have_to_fit() {
+        let encoder = png::Encoder::new(Vec::new(), 4, 2);
+        let mut png = ParallelPng::new(encoder, 4, 2).unwrap();
+        assert!(png.write_rows(&noise(3, 1)).is_err());
+        assert!(png.write_rows(&noise(4, 3)).is_err());
+
+        png.write_rows(&noise(4, 1)).unwrap();
+        assert!(png.finish().is_err());
+    }
+
+    #[test]
+    fn images_need_pixels() {
+        for (width, height) in [(0, 0), (4, 0), (0, 4)] {
+            let encoder = png::Encoder::new(Vec::new(), width as u32, height as u32);
+            let err = ParallelPng::new(encoder, width, height).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+            let mut png = Vec::new();
+            let empty = ColorMap::new(width, height);
+            assert!(png_crate::write(&mut png, &empty).is_err());
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use rayon::prelude::*;
+
+use crate::{CancelToken, ColorMap, Mandelbrot};
+
+///Block size of the first and coarsest pass
+const FIRST_STEP: usize = 8;
+
+///Renders a set in multiple passes, every pass doubles the resolution of the previous one.
+///
+///Samples of earlier passes are kept, so every pixel is only calculated once.
+pub struct Progressive {
+    set: Mandelbrot,
+    iterations: ColorMap<u64>,
+    ///Block size of the last finished pass, 0 if no pass was rendered yet
+    step: usize,
+    cancel: CancelToken,
+}
+
+impl Progressive {
+    pub fn new(set: Mandelbrot) -> Self {
+        let iterations = ColorMap::new(set.image_size.x, set.image_size.y);
+
+        Self {
+            set,
+            iterations,
+            step: 0,
+            cancel: CancelToken::new(),
+        }
+    }
+
+    ///Stop calculating as soon as the token gets cancelled
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    pub fn set(&self) -> &Mandelbrot {
+        &self.set
+    }
+
+    ///Block size of the last finished pass, 1 means full resolution
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.step == 1
+    }
+
+    ///Calculate the next pass, returns its block size or `None` if the image is already complete or the render got cancelled
+    pub fn next_pass(&mut self) -> Option<usize> {
+        let step = match self.step {
+            0 => FIRST_STEP,
+            1 => return None,
+            s => s / 2,
+        };
+        let previous = self.step;
+
+        let set = &self.set;
+        let cancel = &self.cancel;
+        let width = self.iterations.width;
+
+        //Rows of this pass below the real axis whose mirror image is part of the pass as well
+        let mirrored = |y: usize| {
+            set.mirror()
+                .and_then(|mirror| mirror.checked_sub(y))
+                .filter(|&source| source < y && source % step == 0)
+        };
+
+        self.iterations
+            .data
+            .par_chunks_mut(width.max(1))
+            .enumerate()
+            .filter(|&(y, _)| y % step == 0 && mirrored(y).is_none())
+            .for_each(|(y, row)| {
+                if cancel.is_cancelled() {
+                    return;
+                }
+
+                for x in (0..width).step_by(step) {
+                    //Sample is already known from an earlier pass
+                    if previous != 0 && x % previous == 0 && y % previous == 0 {
+                        continue;
+                    }
+
+                    row[x] = set.get_pixel(x as f64, y as f64);
+                }
+            });
+
+        //Rows of an aborted pass may be missing
+        if cancel.is_cancelled() {
+            return None;
+        }
+
+        for y in (0..self.iterations.height).step_by(step) {
+            if let Some(source) = mirrored(y) {
+                let start = source * width;
+                self.iterations
+                    .data
+                    .copy_within(start..start + width, y * width);
+            }
+        }
+
+        self.step = step;
+        Some(step)
+    }
+
+    ///Iteration count of a pixel, taken from the top left corner of its block
+    fn block_value(&self, x: usize, y: usize) -> u64 {
+        let step = self.step.max(1);
+        self.iterations[(x - x % step, y - y % step)]
+    }
+
+    ///Iteration counts of the last pass, upscaled to the full image size
+    pub fn iterations(&self) -> ColorMap<u64> {
+        let width = self.iterations.width;
+        let data = (0..self.iterations.data.len())
+            .into_par_iter()
+            .map(|i| self.block_value(i % width, i / width))
+            .collect();
+
+        ColorMap {
+            width,
+            height: self.iterations.height,
+            data,
+        }
+    }
+
+    ///Colors of the last pass, upscaled to the full image size
+    pub fn color_map(&self) -> ColorMap<[u8; 3]> {
+        let width = self.iterations.width;
+        let data = (0..self.iterations.data.len())
+            .into_par_iter()
+            .map(|i| {
+                let iter = self.block_value(i % width, i / width);
+
+                self.set.color(iter)
+            })
+            .collect();
+
+        ColorMap {
+            width,
+            height: self.iterations.height,
+            data,
+        }
+    }
+
+    ///Colors of the last pass in linear light, upscaled to the full image size
+    pub fn color_map_linear(&self) -> ColorMap<[f32; 3]> {
+        let width = self.iterations.width;
+        let data = (0..self.iterations.data.len())
+            .into_par_iter()
+            .map(|i| {
+                let iter = self.block_value(i % width, i / width);
+
+                self.set.color_linear(iter)
+            })
+            .collect();
+
+        ColorMap {
+            width,
+            height: self.iterations.height,
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dim;
+
+    fn set() -> Mandelbrot {
+        Mandelbrot::from_range(Dim::new(50, 30), (-0.8, -0.7), (0.05, 0.125)).max_iterations(300)
+    }
+
+    #[test]
+    fn passes_halve_the_block_size() {
+        let mut progressive = Progressive::new(set());
+        assert_eq!(progressive.step(), 0);
+
+        let steps: Vec<usize> = std::iter::from_fn(|| progressive.next_pass()).collect();
+        assert_eq!(steps, [8, 4, 2, 1]);
+        assert!(progressive.is_done());
+        assert_eq!(progressive.next_pass(), None);
+    }
+
+    #[test]
+    fn blocks_show_their_top_left_sample() {
+        let set = set();
+        let full = |x: usize, y: usize| set.get_pixel(x as f64, y as f64);
+        let mut progressive = Progressive::new(set.clone());
+
+        while let Some(step) = progressive.next_pass() {
+            let iterations = progressive.iterations();
+            assert_eq!((iterations.width, iterations.height), (50, 30));
+
+            for y in 0..30 {
+                for x in 0..50 {
+                    assert_eq!(iterations[(x, y)], full(x - x % step, y - y % step));
+                }
+            }
+        }
+
+        let colors = progressive.color_map();
+        let expected = progressive.iterations().map(|&iter| set.color(iter));
+        assert_eq!(colors.data, expected.data);
+
+        let linear = progressive.color_map_linear();
+        let quantized = linear.map(|&color| crate::color::from_linear(color));
+        assert_eq!(quantized.data, colors.data);
+    }
+
+    #[test]
+    fn cancelled_passes_are_dropped() {
+        let token = CancelToken::new();
+        let mut progressive = Progressive::new(set()).cancel_token(token.clone());
+        assert_eq!(progressive.next_pass(), Some(8));
+
+        token.cancel();
+        assert_eq!(progressive.next_pass(), None);
+        assert_eq!(progressive.step(), 8);
+        assert!(!progressive.is_done());
+    }
+
+    #[test]
+    fn mirrored_passes_match_the_whole_map() {
+        //The real axis lies between the rows 49 and 50, 64 pixels per unit
+        let set = Mandelbrot::from_range(Dim::new(160, 100), (-2., 0.5), (-49.5 / 64., 50.5 / 64.))
+            .max_iterations(100);
+        assert_eq!(set.mirror(), Some(99));
+
+        let mut progressive = Progressive::new(set.clone());
+        while progressive.next_pass().is_some() {}
+        assert_eq!(
+            progressive.iterations().data,
+            set.region(0, 0, 160, 100).data
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//!Median cut color quantization for formats with a limited palette, like gif.
+
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+///Colors with the number of pixels that have them
+pub type Histogram = HashMap<[u8; 3], u64>;
+
+///Channel with the widest spread of values in a bucket and the spread
+fn widest_channel(bucket: &[([u8; 3], u64)]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = bucket.iter().map(|(color, _)| color[channel]);
+            let (min, max) = values.fold((u8::MAX, 0), |(min, max), v| (min.min(v), max.max(v)));
+            (channel, max.saturating_sub(min))
+        })
+        .max_by_key(|&(_, spread)| spread)
+        .unwrap()
+}
+
+///Mean of the colors in a bucket, weighted by their pixel count
+fn average(bucket: &[([u8; 3], u64)]) -> [u8; 3] {
+    let total: u64 = bucket.iter().map(|(_, count)| count).sum::<u64>().max(1);
+
+    [0, 1, 2].map(|channel| {
+        let sum: u64 = bucket
+            .iter()
+            .map(|(color, count)| color[channel] as u64 * count)
+            .sum();
+        ((sum + total / 2) / total) as u8
+    })
+}
+
+///Reduce the colors of a histogram to at most `max_colors`.
+///
+///The bucket with the widest channel is split at the median pixel of that channel until there are enough buckets,
+///every bucket becomes the average of its colors.
+pub fn median_cut(histogram: &Histogram, max_colors: usize) -> Vec<[u8; 3]> {
+    let mut colors: Vec<_> = histogram
+        .iter()
+        .map(|(&color, &count)| (color, count))
+        .collect();
+    //Same palette for the same histogram, no matter how the map is ordered
+    colors.sort_unstable();
+
+    let mut buckets = vec![colors];
+
+    while buckets.len() < max_colors {
+        let widest = buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, bucket)| bucket.len() > 1)
+            .map(|(i, bucket)| (i, widest_channel(bucket)))
+            .max_by_key(|&(_, (_, spread))| spread);
+        let Some((index, (channel, _))) = widest else {
+            break;
+        };
+
+        let mut bucket = buckets.swap_remove(index);
+        bucket.sort_unstable_by_key(|(color, _)| color[channel]);
+
+        let total: u64 = bucket.iter().map(|(_, count)| count).sum();
+        let mut pixels = 0;
+        let median = bucket
+            .iter()
+            .position(|(_, count)| {
+                pixels += count;
+                pixels * 2 >= total
+            })
+            .unwrap_or(0);
+
+        //Both halves keep at least one color
+        let rest = bucket.split_off((median + 1).min(bucket.len() - 1));
+        buckets.push(bucket);
+        buckets.push(rest);
+    }
+
+    buckets
+        .iter()
+        .filter(|bucket| !bucket.is_empty())
+        .map(|bucket| average(bucket))
+        .collect()
+}
+
+///Index of the closest palette color
+pub fn nearest(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance = |other: &[u8; 3]| -> u32 {
+        (0..3)
+            .map(|i| (color[i] as i32 - other[i] as i32).pow(2) as u32)
+            .sum()
+    };
+
+    (0..palette.len())
+        .min_by_key(|&i| distance(&palette[i]))
+        .unwrap_or(0) as u8
+}
+
+///Palette index of every color in the histogram
+pub fn lookup(palette: &[[u8; 3]], histogram: &Histogram) -> HashMap<[u8; 3], u8> {
+    let colors: Vec<[u8; 3]> = histogram.keys().copied().collect();
+
+    colors
+        .par_iter()
+        .map(|&color| (color, nearest(palette, color)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(colors: &[([u8; 3], u64)]) -> Histogram {
+        colors.iter().copied().collect()
+    }
+
+    #[test]
+    fn few_colors_stay_exact() {
+        let histogram = histogram(&[([255, 0, 0], 3), ([0, 0, 255], 1), ([10, 20, 30], 7)]);
+        let mut palette = median_cut(&histogram, 256);
+        palette.sort_unstable();
+        assert_eq!(palette, [[0, 0, 255], [10, 20, 30], [255, 0, 0]]);
+    }
+
+    #[test]
+    fn reduces_to_the_palette_size() {
+        let gray: Vec<_> = (0..=255).map(|v| ([v, v, v], 1)).collect();
+        let palette = median_cut(&histogram(&gray), 16);
+        assert_eq!(palette.len(), 16);
+        assert_eq!(palette, median_cut(&histogram(&gray), 16));
+
+        //Every bucket holds 16 neighboring grays
+        let mut palette = palette;
+        palette.sort_unstable();
+        assert_eq!(palette[0], [8, 8, 8]);
+        assert_eq!(palette[15], [248, 248, 248]);
+    }
+
+    #[test]
+    fn averages_are_weighted_by_pixels() {
+        let histogram = histogram(&[([0, 0, 0], 3), ([100, 0, 0], 1)]);
+        assert_eq!(median_cut(&histogram, 1), [[25, 0, 0]]);
+        assert!(median_cut(&Histogram::new(), 4).is_empty());
+    }
+
+    #[test]
+    fn looks_up_the_nearest_color() {
+        let palette = [[0, 0, 0], [255, 255, 255], [200, 0, 0]];
+        assert_eq!(nearest(&palette, [20, 10, 0]), 0);
+        assert_eq!(nearest(&palette, [180, 30, 20]), 2);
+        assert_eq!(nearest(&palette, [180, 180, 200]), 1);
+
+        let lookup = lookup(
+            &palette,
+            &histogram(&[([20, 10, 0], 1), ([250, 250, 250], 1)]),
+        );
+        assert_eq!(lookup.len(), 2);
+        assert_eq!(lookup[&[250, 250, 250]], 1);
+    }
+}
@@ -0,0 +1,539 @@
+//!Raw values of every pixel instead of 8 bit colors, for analysis in other tools and for recoloring later.
+//!
+//!Supported are numpy `.npy` arrays, 16 bit grayscale png and `.pfm` float images.
+//!Every file holds one channel, the loaders return it as `f64` values.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::ColorMap;
+
+///Raw values of a single point
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sample {
+    pub iterations: u64,
+    ///Continuous iteration count, without the bands of the whole numbers
+    pub smooth: f64,
+    ///Estimated distance to the set in the complex plane, 0 inside the set
+    pub distance: f64,
+}
+
+///Value of a sample that gets exported
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Iterations,
+    Smooth,
+    Distance,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 3] = [Channel::Iterations, Channel::Smooth, Channel::Distance];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Iterations => "iterations",
+            Channel::Smooth => "smooth",
+            Channel::Distance => "distance",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+impl Sample {
+    pub fn get(&self, channel: Channel) -> f64 {
+        match channel {
+            Channel::Iterations => self.iterations as f64,
+            Channel::Smooth => self.smooth,
+            Channel::Distance => self.distance,
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+///Save a channel, the format comes from the extension: `npy`, `png` or `pfm`
+pub fn save(path: &Path, map: &ColorMap<Sample>, channel: Channel) -> io::Result<()> {
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    match extension.to_lowercase().as_str() {
+        "npy" => npy::save(path, map, channel),
+        "png" => png16::save(path, &map.map(|sample| sample.get(channel)), channel),
+        "pfm" => pfm::save(path, &map.map(|sample| sample.get(channel))),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown data format '{extension}', use npy, png or pfm"),
+        )),
+    }
+}
+
+///Load a channel that was saved with `save`, the format comes from the extension
+pub fn load(path: &Path) -> io::Result<ColorMap<f64>> {
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    match extension.to_lowercase().as_str() {
+        "npy" => npy::load(path),
+        "png" => png16::load(path),
+        "pfm" => pfm::load(path),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown data format '{extension}', use npy, png or pfm"),
+        )),
+    }
+}
+
+pub mod npy {
+    use super::*;
+
+    const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+
+    ///Save a channel as a 2D array with a row per image row.
+    ///
+    ///Iterations are stored as `u64`, the other channels as `f32`.
+    pub fn save(path: &Path, map: &ColorMap<Sample>, channel: Channel) -> io::Result<()> {
+        let descr = match channel {
+            Channel::Iterations => "<u8",
+            _ => "<f4",
+        };
+        let mut header = format!(
+            "{{'descr': '{descr}', 'fortran_order': False, 'shape': ({}, {}), }}",
+            map.height, map.width
+        );
+        //The data starts at a multiple of 64 bytes, the header ends with a newline
+        let length = MAGIC.len() + 2 + header.len() + 1;
+        header.push_str(&" ".repeat((64 - length % 64) % 64));
+        header.push('\n');
+
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&(header.len() as u16).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+
+        for sample in &map.data {
+            match channel {
+                Channel::Iterations => w.write_all(&sample.iterations.to_le_bytes())?,
+                _ => w.write_all(&(sample.get(channel) as f32).to_le_bytes())?,
+            }
+        }
+        w.flush()
+    }
+
+    ///Value of a key in the header dictionary, up to the next comma outside of parentheses
+    fn field<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+        let start = header.find(&format!("'{key}'"))? + key.len() + 2;
+        let rest = header[start..].trim_start().strip_prefix(':')?.trim_start();
+
+        let end = match rest.strip_prefix('(') {
+            Some(tuple) => tuple.find(')')? + 2,
+            None => rest.find([',', '}'])?,
+        };
+        Some(rest[..end].trim())
+    }
+
+    ///Load a 2D array of little endian integers or floats
+    pub fn load(path: &Path) -> io::Result<ColorMap<f64>> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+            return Err(invalid("Not a npy file"));
+        }
+
+        //Version 1 has a 2 byte header length, later versions 4 bytes
+        let (length, start) = match bytes[6] {
+            1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+            _ if bytes.len() >= 12 => (
+                u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+                12,
+            ),
+            _ => return Err(invalid("Truncated npy header")),
+        };
+        let header = bytes
+            .get(start..start + length)
+            .and_then(|header| std::str::from_utf8(header).ok())
+            .ok_or_else(|| invalid("Invalid npy header"))?;
+        let data = &bytes[start + length..];
+
+        if field(header, "fortran_order") != Some("False") {
+            return Err(invalid("Only arrays in row order are supported"));
+        }
+
+        let shape: Vec<usize> = field(header, "shape")
+            .ok_or_else(|| invalid("The npy header has no shape"))?
+            .trim_matches(|c| c == '(' || c == ')')
+            .split(',')
+            .filter(|n| !n.trim().is_empty())
+            .map(|n| n.trim().parse().map_err(|_| invalid("Invalid npy shape")))
+            .collect::<io::Result<_>>()?;
+        let [height, width] = shape[..] else {
+            return Err(invalid("Only 2D arrays are supported"));
+        };
+
+        let descr = field(header, "descr")
+            .ok_or_else(|| invalid("The npy header has no type"))?
+            .trim_matches('\'');
+        let (size, convert): (usize, fn(&[u8]) -> f64) = match descr {
+            "|u1" => (1, |b| b[0] as f64),
+            "<u2" => (2, |b| u16::from_le_bytes([b[0], b[1]]) as f64),
+            "<u4" => (4, |b| u32::from_le_bytes(b.try_into().unwrap()) as f64),
+            "<u8" => (8, |b| u64::from_le_bytes(b.try_into().unwrap()) as f64),
+            "<i4" => (4, |b| i32::from_le_bytes(b.try_into().unwrap()) as f64),
+            "<i8" => (8, |b| i64::from_le_bytes(b.try_into().unwrap()) as f64),
+            "<f4" => (4, |b| f32::from_le_bytes(b.try_into().unwrap()) as f64),
+            "<f8" => (8, |b| f64::from_le_bytes(b.try_into().unwrap())),
+            _ => return Err(invalid(format!("Unsupported npy type '{descr}'"))),
+        };
+
+        let bytes = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(size))
+            .ok_or_else(|| invalid("Invalid npy shape"))?;
+        if data.len() < bytes {
+            return Err(invalid("The npy file is truncated"));
+        }
+        Ok(ColorMap {
+            width,
+            height,
+            data: data
+                .chunks_exact(size)
+                .take(width * height)
+                .map(convert)
+                .collect(),
+        })
+    }
+}
+
+pub mod png16 {
+    use super::*;
+
+    ///Save values as 16 bit grayscale png, scaled from their minimum to their maximum.
+    ///
+    ///The range is kept in text chunks, so `load` returns the original values with 16 bit precision.
+    pub fn save(path: &Path, values: &ColorMap<f64>, channel: Channel) -> io::Result<()> {
+        let finite = values.data.iter().copied().filter(|v| v.is_finite());
+        let (min, max) = finite.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+            (min.min(v), max.max(v))
+        });
+        let (min, max) = if min <= max { (min, max) } else { (0., 0.) };
+        let scale = if max > min { 65535. / (max - min) } else { 0. };
+
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            values.width as u32,
+            values.height as u32,
+        );
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        encoder.add_text_chunk("Channel".to_string(), channel.name().to_string())?;
+        encoder.add_text_chunk("Minimum".to_string(), min.to_string())?;
+        encoder.add_text_chunk("Maximum".to_string(), max.to_string())?;
+
+        //Png stores 16 bit values big endian
+        let data: Vec<u8> = values
+            .data
+            .iter()
+            .flat_map(|&v| {
+                let v = if v.is_finite() { v } else { max };
+                (((v - min) * scale).round() as u16).to_be_bytes()
+            })
+            .collect();
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    ///Load a grayscale png, values are scaled back to the range of its text chunks or from 0 to 1
+    pub fn load(path: &Path) -> io::Result<ColorMap<f64>> {
+        let decoder = png::Decoder::new(File::open(path)?);
+        let mut reader = decoder.read_info()?;
+
+        let text = |keyword: &str| {
+            let chunks = &reader.info().uncompressed_latin1_text;
+            let chunk = chunks.iter().find(|chunk| chunk.keyword == keyword)?;
+            chunk.text.parse::<f64>().ok()
+        };
+        let (min, max) = (text("Minimum").unwrap_or(0.), text("Maximum").unwrap_or(1.));
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        if info.color_type != png::ColorType::Grayscale {
+            return Err(invalid("Raw data has to be a grayscale png"));
+        }
+
+        let data: Vec<f64> = match info.bit_depth {
+            png::BitDepth::Sixteen => buf[..info.buffer_size()]
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as f64 / 65535.)
+                .collect(),
+            png::BitDepth::Eight => buf[..info.buffer_size()]
+                .iter()
+                .map(|&b| b as f64 / 255.)
+                .collect(),
+            _ => return Err(invalid("Only 8 and 16 bit grayscale png are supported")),
+        };
+
+        Ok(ColorMap {
+            width: info.width as usize,
+            height: info.height as usize,
+            data: data.into_iter().map(|v| min + v * (max - min)).collect(),
+        })
+    }
+}
+
+pub mod pfm {
+    use super::*;
+
+    ///Save values as grayscale float image, precision drops to `f32`
+    pub fn save(path: &Path, values: &ColorMap<f64>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+
+        //A negative scale means little endian, rows go from the bottom to the top
+        write!(w, "Pf\n{} {}\n-1.0\n", values.width, values.height)?;
+        for row in values.data.chunks(values.width.max(1)).rev() {
+            for &v in row {
+                w.write_all(&(v as f32).to_le_bytes())?;
+            }
+        }
+        w.flush()
+    }
+
+    ///Load a grayscale float image, color images use their first channel
+    pub fn load(path: &Path) -> io::Result<ColorMap<f64>> {
+        let bytes = fs::read(path)?;
+
+        //Three whitespace separated header fields after the type, then a single whitespace
+        let mut fields = Vec::new();
+        let mut position = 0;
+        while fields.len() < 4 {
+            while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+                position += 1;
+            }
+            let start = position;
+            while bytes
+                .get(position)
+                .is_some_and(|b| !b.is_ascii_whitespace())
+            {
+                position += 1;
+            }
+            if start == position {
+                return Err(invalid("Truncated pfm header"));
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..position]).to_string());
+        }
+        let data = bytes
+            .get(position + 1..)
+            .ok_or_else(|| invalid("Truncated pfm header"))?;
+
+        let channels = match fields[0].as_str() {
+            "Pf" => 1,
+            "PF" => 3,
+            _ => return Err(invalid("Not a pfm file")),
+        };
+        let size = |i: usize| {
+            fields[i]
+                .parse::<usize>()
+                .map_err(|_| invalid("Invalid pfm size"))
+        };
+        let (width, height) = (size(1)?, size(2)?);
+        let scale: f64 = fields[3]
+            .parse()
+            .map_err(|_| invalid("Invalid pfm scale"))?;
+
+        let size = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(channels * 4))
+            .ok_or_else(|| invalid("Invalid pfm size"))?;
+        if data.len() != size {
+            return Err(invalid(format!(
+                "The pfm file has {} bytes of data instead of {size}",
+                data.len()
+            )));
+        }
+
+        let value = |b: &[u8]| {
+            let b = b.try_into().unwrap();
+            if scale < 0. {
+                f32::from_le_bytes(b) as f64
+            } else {
+                f32::from_be_bytes(b) as f64
+            }
+        };
+        let mut rows: Vec<Vec<f64>> = data
+            .chunks_exact(width.max(1) * channels * 4)
+            .map(|row| {
+                row.chunks_exact(channels * 4)
+                    .map(|p| value(&p[..4]))
+                    .collect()
+            })
+            .collect();
+        rows.reverse();
+
+        Ok(ColorMap {
+            width,
+            height,
+            data: rows.concat(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::{Dim, Mandelbrot};
+
+    fn samples() -> ColorMap<Sample> {
+        Mandelbrot::from_range(Dim::new(24, 16), (-2., 0.47), (-1.12, 1.12))
+            .max_iterations(200)
+            .sample_map()
+    }
+
+    #[test]
+    fn samples_match_the_iterations() {
+        let set = Mandelbrot::from_range(Dim::new(24, 16), (-2., 0.47), (-1.12, 1.12));
+        let samples = set.sample_map();
+
+        assert_eq!(samples.map(|s| s.iterations).data, set.iteration_map().data);
+        for sample in &samples.data {
+            if sample.iterations == set.max_iterations {
+                assert_eq!(sample.distance, 0.);
+            } else {
+                assert!(sample.distance > 0.);
+                assert!(sample.smooth > 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn npy_round_trip() {
+        let dir = TempDir::new("raw");
+        let samples = samples();
+        let path = dir.join("round-trip.npy");
+
+        save(&path, &samples, Channel::Iterations).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(
+            (10 + u16::from_le_bytes([bytes[8], bytes[9]]) as usize) % 64,
+            0
+        );
+        let loaded = load(&path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (24, 16));
+        assert_eq!(loaded.data, samples.map(|s| s.iterations as f64).data);
+
+        save(&path, &samples, Channel::Smooth).unwrap();
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded.data, samples.map(|s| s.smooth as f32 as f64).data);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let dir = TempDir::new("raw");
+        let samples = samples();
+        let path = dir.join("round-trip.png");
+
+        save(&path, &samples, Channel::Smooth).unwrap();
+        let loaded = load(&path).unwrap();
+        let smooth = samples.map(|s| s.smooth);
+        let (min, max) = smooth
+            .data
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(min, max), &v| {
+                (min.min(v), max.max(v))
+            });
+        for (a, b) in loaded.data.iter().zip(&smooth.data) {
+            assert!((a - b).abs() <= (max - min) / 65535.);
+        }
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let dir = TempDir::new("raw");
+        let samples = samples();
+        let path = dir.join("round-trip.pfm");
+
+        save(&path, &samples, Channel::Distance).unwrap();
+        let loaded = load(&path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (24, 16));
+        assert_eq!(loaded.data, samples.map(|s| s.distance as f32 as f64).data);
+    }
+
+    #[test]
+    fn pfm_rejects_broken_files() {
+        let dir = TempDir::new("raw");
+        let path = dir.join("broken.pfm");
+        let pixel = 1_f32.to_le_bytes();
+
+        for (bytes, error) in [
+            (b"Pf\n2 1\n-1.0".to_vec(), "Truncated pfm header"),
+            (b"Pf\n2 1\n".to_vec(), "Truncated pfm header"),
+            (b"Pf\n2 1\n-1.0\n".to_vec(), "0 bytes of data instead of 8"),
+            ([&b"Pf\n2 1\n-1.0\n"[..], &pixel].concat(), "4 bytes"),
+            (
+                [&b"Pf\n1 1\n-1.0\n"[..], &pixel, &pixel].concat(),
+                "8 bytes",
+            ),
+            (b"Pf\n-2 1\n-1.0\n".to_vec(), "Invalid pfm size"),
+            (b"P6\n2 1\n-1.0\n".to_vec(), "Not a pfm file"),
+        ] {
+            fs::write(&path, bytes).unwrap();
+            let err = load(&path).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains(error), "{err}");
+        }
+
+        fs::write(&path, [&b"Pf\n1 1\n-1.0\n"[..], &pixel].concat()).unwrap();
+        assert_eq!(load(&path).unwrap().data, vec![1.]);
+    }
+
+    #[test]
+    fn npy_rejects_truncated_files() {
+        let dir = TempDir::new("raw");
+        let samples = samples();
+        let path = dir.join("truncated.npy");
+
+        save(&path, &samples, Channel::Iterations).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(
+            load(&path).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        fs::write(&path, &bytes[..20]).unwrap();
+        assert_eq!(
+            load(&path).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn npy_rejects_overflowing_shapes() {
+        let dir = TempDir::new("raw");
+        let path = dir.join("huge.npy");
+
+        let header = format!(
+            "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, 4), }}\n",
+            usize::MAX / 2
+        );
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend([0; 64]);
+        fs::write(&path, bytes).unwrap();
+
+        assert_eq!(
+            load(&path).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn unknown_formats_are_an_error() {
+        let dir = TempDir::new("raw");
+        let err = save(&dir.join("data.txt"), &samples(), Channel::Smooth).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
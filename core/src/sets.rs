@@ -1,17 +1,68 @@
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
 ///2 Dimensions
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Dim<T> {
-    x: T,
-    y: T,
+    pub x: T,
+    pub y: T,
 }
 impl<T> Dim<T> {
-    #[allow(dead_code)]
-    fn new(x: T, y: T) -> Self {
+    pub fn new(x: T, y: T) -> Self {
         Self { x, y }
     }
 }
 
-use crate::color;
+///How the iteration count of every pixel gets calculated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    ///Calculate every single pixel
+    BruteForce,
+    ///Mariani–Silver, fill rectangles with a uniform border and subdivide the others
+    Subdivision,
+}
+
+///Formula that gets iterated for every pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fractal {
+    Mandelbrot,
+    BurningShip,
+}
+impl Fractal {
+    pub const ALL: [Fractal; 2] = [Fractal::Mandelbrot, Fractal::BurningShip];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Fractal::Mandelbrot => "mandelbrot",
+            Fractal::BurningShip => "burning-ship",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    ///Whether the set is symmetric about the real axis
+    pub fn is_symmetric(self) -> bool {
+        match self {
+            Fractal::Mandelbrot => true,
+            Fractal::BurningShip => false,
+        }
+    }
+}
+
+use crate::color::{self, Palette};
+use crate::raw::Sample;
+use crate::{subdivision, CancelToken, ColorMap};
+
+///Escape radius of the continuous iteration count and the distance estimate,
+///they are only accurate far outside of the usual radius
+const SAMPLE_RADIUS: f64 = 1e3;
+///Rows that are calculated between checks for cancellation while panning
+const BAND_HEIGHT: usize = 64;
+#[derive(Clone)]
 pub struct Mandelbrot {
     pub image_size: Dim<usize>,
     pub x_range: (f64, f64),
@@ -20,6 +71,13 @@ pub struct Mandelbrot {
     scale: Dim<f64>,
     pub radius: f64,
     pub max_iterations: u64,
+    pub fractal: Fractal,
+    pub palette: Palette,
+    ///Shifts the colors along the palette, from 0 to 1
+    pub palette_offset: f32,
+    ///Counterclockwise rotation of the view around its center in radians
+    pub rotation: f64,
+    pub strategy: Strategy,
 }
 #[allow(dead_code)]
 
@@ -36,6 +94,11 @@ impl Mandelbrot {
             scale: Dim { x: 0., y: 0. },
             radius: 2.,
             max_iterations: 1_000,
+            fractal: Fractal::Mandelbrot,
+            palette: Palette::Rainbow,
+            palette_offset: 0.,
+            rotation: 0.,
+            strategy: Strategy::BruteForce,
         };
         inst.calculate_offset();
         inst.calculate_scale();
@@ -51,6 +114,11 @@ impl Mandelbrot {
             scale: Dim { x: 0., y: 0. },
             radius: 2.,
             max_iterations: 1_000,
+            fractal: Fractal::Mandelbrot,
+            palette: Palette::Rainbow,
+            palette_offset: 0.,
+            rotation: 0.,
+            strategy: Strategy::BruteForce,
         };
         inst.calculate_offset();
         inst.calculate_scale();
@@ -68,6 +136,31 @@ impl Mandelbrot {
         self
     }
 
+    pub fn fractal(mut self, fractal: Fractal) -> Self {
+        self.fractal = fractal;
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn palette_offset(mut self, offset: f32) -> Self {
+        self.palette_offset = offset;
+        self
+    }
+
+    pub fn rotation(mut self, angle: f64) -> Self {
+        self.rotation = angle;
+        self
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
     fn calculate_offset(&mut self) {
         let x_offset = (self.x_range.0 + self.x_range.1) / 2.;
         let y_offset = (self.y_range.0 + self.y_range.1) / 2.;
@@ -97,21 +190,63 @@ impl Mandelbrot {
         self.calculate_scale();
     }
 
-    ///Get value of the mandelbrot set according to a pixel on the screen
-    pub fn get_pixel(&self, px: f64, py: f64) -> u64 {
+    ///Point in the complex plane of a pixel on the screen
+    pub fn point(&self, px: f64, py: f64) -> (f64, f64) {
         let x0 = px - (self.image_size.x / 2) as f64;
         let y0 = py - (self.image_size.y / 2) as f64;
 
-        let x0 = (x0 / self.scale.x) + self.offset.x;
-        let y0 = (y0 / self.scale.y) + self.offset.y;
+        let (mut x0, mut y0) = (x0 / self.scale.x, y0 / self.scale.y);
+        if self.rotation != 0. {
+            let (sin, cos) = self.rotation.sin_cos();
+            (x0, y0) = (x0 * cos - y0 * sin, x0 * sin + y0 * cos);
+        }
+
+        (x0 + self.offset.x, y0 + self.sample_offset_y())
+    }
+
+    ///Twice the row of the real axis, rounded so the axis lies on a row or exactly between two rows.
+    ///
+    ///`None` if the view isn't symmetric about the real axis. The axis may be outside of the view,
+    ///so views that got panned away from it keep the same rows.
+    fn snapped_axis(&self) -> Option<f64> {
+        if !self.fractal.is_symmetric() || self.rotation != 0. {
+            return None;
+        }
+
+        let axis = 2. * ((self.image_size.y / 2) as f64 - self.offset.y * self.scale.y);
+        axis.is_finite().then_some(axis.round())
+    }
 
+    ///Imaginary part of the center the rows get sampled around.
+    ///
+    ///Symmetric views get their rows moved by at most a quarter pixel, so that every row
+    ///below the real axis samples the mirror image of a row above it. The alternative, mirroring to the
+    ///nearest row, would shift only the mirrored half by up to half a pixel and leave a seam at the axis.
+    fn sample_offset_y(&self) -> f64 {
+        match self.snapped_axis() {
+            Some(axis) => ((self.image_size.y / 2) as f64 - axis / 2.) / self.scale.y,
+            None => self.offset.y,
+        }
+    }
+
+    ///Get value of the mandelbrot set according to a pixel on the screen
+    pub fn get_pixel(&self, px: f64, py: f64) -> u64 {
+        let (x0, y0) = self.point(px, py);
+        self.iterate(x0, y0)
+    }
+
+    ///Iteration count of a point in the complex plane
+    pub fn iterate(&self, x0: f64, y0: f64) -> u64 {
         let mut x = 0.0;
         let mut y = 0.0;
         let mut iteration = 0_u64;
 
         while ((x * x + y * y) <= self.radius * self.radius) && (iteration < self.max_iterations) {
             let xtemp = x * x - y * y + x0;
-            y = 2. * x * y + y0;
+            y = match self.fractal {
+                Fractal::Mandelbrot => 2. * x * y + y0,
+                Fractal::BurningShip => (2. * x * y).abs() + y0,
+            };
             x = xtemp;
             iteration += 1;
         }
@@ -119,6 +254,103 @@ impl Mandelbrot {
         iteration
     }
 
+    ///Iteration count, continuous iteration count and distance estimate of a point.
+    ///
+    ///The iteration count is the same as from `iterate`. Escaped points keep iterating
+    ///up to a larger radius for the other two values, points inside the set get a distance of 0.
+    pub fn sample(&self, x0: f64, y0: f64) -> Sample {
+        let (mut x, mut y) = (0.0, 0.0);
+        //Derivative with respect to the real part of the point
+        let (mut dx, mut dy) = (0.0, 0.0);
+        let mut iteration = 0_u64;
+        let mut escaped = None;
+
+        loop {
+            let magnitude = x * x + y * y;
+            if escaped.is_none() && magnitude > self.radius * self.radius {
+                escaped = Some(iteration);
+            }
+            if magnitude > SAMPLE_RADIUS * SAMPLE_RADIUS
+                || (escaped.is_none() && iteration >= self.max_iterations)
+            {
+                break;
+            }
+
+            let (dx_temp, dy_temp) = (2. * (x * dx - y * dy) + 1., 2. * (y * dx + x * dy));
+            let xtemp = x * x - y * y + x0;
+            (y, dy) = match self.fractal {
+                Fractal::Mandelbrot => (2. * x * y + y0, dy_temp),
+                Fractal::BurningShip => ((2. * x * y).abs() + y0, dy_temp * (x * y).signum()),
+            };
+            (x, dx) = (xtemp, dx_temp);
+            iteration += 1;
+        }
+
+        let Some(iterations) = escaped else {
+            return Sample {
+                iterations: iteration,
+                smooth: iteration as f64,
+                distance: 0.,
+            };
+        };
+
+        let modulus = x.hypot(y);
+        Sample {
+            iterations,
+            smooth: iteration as f64 + 1. - modulus.ln().log2(),
+            distance: 2. * modulus * modulus.ln() / dx.hypot(dy),
+        }
+    }
+
+    ///Raw values of every pixel on the screen
+    pub fn sample_map(&self) -> ColorMap<Sample> {
+        let width = self.image_size.x;
+        let mut map = ColorMap::new(width, self.image_size.y);
+
+        map.data
+            .par_chunks_mut(width.max(1))
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, sample) in row.iter_mut().enumerate() {
+                    let (x0, y0) = self.point(x as f64, y as f64);
+                    *sample = self.sample(x0, y0);
+                }
+            });
+
+        map
+    }
+
+    ///Color of a continuous iteration count, like one loaded from a raw data file
+    pub fn color_value(&self, value: f64) -> [u8; 3] {
+        let t = color::scale::exponential_value(value.max(0.) as f32) + self.palette_offset;
+        self.palette.color(t.rem_euclid(1.))
+    }
+
+    ///Color of a pixel with the given iteration count, the same as quantizing `color_linear`
+    pub fn color(&self, iteration: u64) -> [u8; 3] {
+        color::from_linear(self.color_linear(iteration))
+    }
+
+    ///Color of a pixel in linear light, straight from the palette without 8 bit quantization
+    pub fn color_linear(&self, iteration: u64) -> [f32; 3] {
+        let t = color::scale::exponential(iteration) + self.palette_offset;
+        self.palette.color_linear(t.rem_euclid(1.))
+    }
+
+    ///Linear colors of every pixel on the screen, for high dynamic range output
+    pub fn linear_map(&self) -> ColorMap<[f32; 3]> {
+        let iterations = self.iteration_map();
+        ColorMap {
+            width: iterations.width,
+            height: iterations.height,
+            data: iterations
+                .data
+                .par_iter()
+                .map(|&iter| self.color_linear(iter))
+                .collect(),
+        }
+    }
+
     ///Get a 2D Vector of colors for every single pixel on the screen
     pub fn get_color_map(&self) -> Vec<Vec<[u8; 3]>> {
         let x_range = 0..self.image_size.x;
@@ -132,10 +364,374 @@ impl Mandelbrot {
                         //Get iteration count
                         let iter = self.get_pixel(x as f64, y as f64);
 
-                        color::from_iterations(iter, color::scale::exponential)
+                        self.color(iter)
                     })
                     .collect()
             })
             .collect()
     }
+
+    ///Get the iteration count of every pixel on the screen, calculated with the selected strategy
+    pub fn iteration_map(&self) -> ColorMap<u64> {
+        self.iteration_region(0, 0, self.image_size.x, self.image_size.y)
+    }
+
+    ///Iteration counts of a rectangle of pixels, rows below the real axis are copied from
+    ///their mirror images above it when both are part of the rectangle
+    pub fn iteration_region(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> ColorMap<u64> {
+        self.mirrored_region(x, y, width, height, &mut HashMap::new(), 0)
+    }
+
+    ///Sum of the row numbers of a row and its mirror image about the real axis.
+    ///
+    ///The rows get sampled so that the axis lies on a row or exactly between two rows,
+    ///see `sample_offset_y`. `None` if no row has a mirror image.
+    pub(crate) fn mirror(&self) -> Option<usize> {
+        let height = self.image_size.y;
+        self.snapped_axis()
+            .filter(|&axis| height >= 2 && (1. ..=2. * (height - 1) as f64).contains(&axis))
+            .map(|axis| axis as usize)
+    }
+
+    ///Like `iteration_region`, but mirror images above the rectangle are taken out of `kept`.
+    ///
+    ///Rows of the rectangle that are the mirror image of a row further down get put into `kept`
+    ///if they are at most `keep` rows away from the axis, so `kept` never holds more than `keep` rows.
+    fn mirrored_region(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        kept: &mut HashMap<usize, Vec<u64>>,
+        keep: usize,
+    ) -> ColorMap<u64> {
+        let Some(mirror) = self.mirror() else {
+            return self.region(x, y, width, height);
+        };
+
+        //Mirror image of every row of the rectangle, if it is known
+        let rows = y..y + height;
+        let sources: Vec<Option<usize>> = rows
+            .clone()
+            .map(|row| {
+                let source = mirror.checked_sub(row)?;
+                (source < row && (source >= y || kept.contains_key(&source))).then_some(source)
+            })
+            .collect();
+
+        //Calculate the other rows in runs, so the strategy still sees whole rectangles
+        let mut map = ColorMap::new(width, height);
+        let mut row = 0;
+        while row < height {
+            let start = row;
+            while row < height && sources[row].is_none() {
+                row += 1;
+            }
+            if row > start {
+                map.paste(0, start, &self.region(x, y + start, width, row - start));
+            }
+            row += 1;
+        }
+
+        for (row, &source) in sources.iter().enumerate() {
+            let target = row * width..(row + 1) * width;
+            match source {
+                Some(source) if source >= y => {
+                    let start = (source - y) * width;
+                    map.data.copy_within(start..start + width, target.start);
+                }
+                Some(source) => map.data[target].copy_from_slice(&kept.remove(&source).unwrap()),
+                None => (),
+            }
+        }
+
+        let near_the_axis = |row: usize| {
+            mirror
+                .checked_sub(2 * row)
+                .is_some_and(|distance| distance > 0 && distance <= 2 * keep)
+        };
+        for row in rows.filter(|&row| near_the_axis(row)) {
+            if (y + height..self.image_size.y).contains(&(mirror - row)) {
+                let start = (row - y) * width;
+                kept.insert(row, map.data[start..start + width].to_vec());
+            }
+        }
+
+        map
+    }
+
+    ///Iteration counts of a rectangle of pixels, calculated with the selected strategy
+    pub fn region(&self, x: usize, y: usize, width: usize, height: usize) -> ColorMap<u64> {
+        match self.strategy {
+            Strategy::BruteForce => self.brute_force(x, y, width, height),
+            Strategy::Subdivision => subdivision::render(self, x, y, width, height),
+        }
+    }
+
+    fn brute_force(&self, x: usize, y: usize, width: usize, height: usize) -> ColorMap<u64> {
+        let mut map = ColorMap::new(width, height);
+
+        map.data
+            .par_chunks_mut(width.max(1))
+            .enumerate()
+            .for_each(|(row_y, row)| {
+                for (row_x, iter) in row.iter_mut().enumerate() {
+                    *iter = self.get_pixel((x + row_x) as f64, (y + row_y) as f64);
+                }
+            });
+
+        map
+    }
+
+    ///Reuse the iteration counts of the previous view after it was moved by whole pixels.
+    ///
+    ///The content gets shifted by `dx` and `dy`, only the exposed strips are calculated.
+    ///Returns `false` if the token got cancelled before they were done.
+    pub fn pan(
+        &self,
+        iterations: &mut ColorMap<u64>,
+        dx: isize,
+        dy: isize,
+        cancel: &CancelToken,
+    ) -> bool {
+        let (width, height) = (self.image_size.x, self.image_size.y);
+
+        //Nothing can be reused
+        if iterations.width != width
+            || iterations.height != height
+            || dx.unsigned_abs() >= width
+            || dy.unsigned_abs() >= height
+        {
+            *iterations = ColorMap::new(width, height);
+            return self.fill(iterations, 0, 0, width, height, cancel);
+        }
+
+        iterations.shift(dx, dy);
+        let (dx_abs, dy_abs) = (dx.unsigned_abs(), dy.unsigned_abs());
+
+        //Exposed rows over the whole width
+        let rows = if dy >= 0 { 0 } else { height - dy_abs };
+        if !self.fill(iterations, 0, rows, width, dy_abs, cancel) {
+            return false;
+        }
+
+        //Exposed columns next to the rows
+        let columns = if dx >= 0 { 0 } else { width - dx_abs };
+        let top = if dy > 0 { dy_abs } else { 0 };
+        self.fill(iterations, columns, top, dx_abs, height - dy_abs, cancel)
+    }
+
+    ///Calculate a rectangle of a map band by band, stops once the token is cancelled
+    fn fill(
+        &self,
+        map: &mut ColorMap<u64>,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        cancel: &CancelToken,
+    ) -> bool {
+        let mut strips = Strips::new(self);
+
+        for band in (y..y + height).step_by(BAND_HEIGHT) {
+            if cancel.is_cancelled() {
+                return false;
+            }
+
+            let rows = BAND_HEIGHT.min(y + height - band);
+            let iterations = if width == self.image_size.x {
+                strips.next(band, rows)
+            } else {
+                self.iteration_region(x, band, width, rows)
+            };
+            map.paste(x, band, &iterations);
+        }
+
+        true
+    }
+
+    ///Compare the selected strategy against brute force, returns every pixel that differs
+    pub fn verify_strategy(&self) -> Vec<(usize, usize)> {
+        let (width, height) = (self.image_size.x, self.image_size.y);
+        let expected = self.brute_force(0, 0, width, height);
+        let actual = self.region(0, 0, width, height);
+
+        (0..expected.data.len())
+            .filter(|&i| expected.data[i] != actual.data[i])
+            .map(|i| (i % expected.width, i / expected.width))
+            .collect()
+    }
+}
+
+///Renders whole rows strip by strip from top to bottom.
+///
+///Rows below the real axis are copied from their mirror images above it, even from earlier strips.
+///Only the rows up to one strip above the axis are kept until their mirror image is rendered,
+///so the memory use stays at about one strip however tall the image is.
+pub struct Strips<'a> {
+    set: &'a Mandelbrot,
+    kept: HashMap<usize, Vec<u64>>,
+}
+
+impl<'a> Strips<'a> {
+    pub fn new(set: &'a Mandelbrot) -> Self {
+        Self {
+            set,
+            kept: HashMap::new(),
+        }
+    }
+
+    ///Iteration counts of the rows from `y` on, strips have to be rendered in order
+    pub fn next(&mut self, y: usize, rows: usize) -> ColorMap<u64> {
+        let width = self.set.image_size.x;
+        self.set
+            .mirrored_region(0, y, width, rows, &mut self.kept, rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///View of 160 by `height` pixels with 64 pixels per unit, so every point is exact
+    fn set(height: usize, center: f64) -> Mandelbrot {
+        let half = height as f64 / 128.;
+        Mandelbrot::from_range(
+            Dim::new(160, height),
+            (-2., 0.5),
+            (center - half, center + half),
+        )
+        .max_iterations(100)
+    }
+
+    fn brute_force(set: &Mandelbrot) -> Vec<u64> {
+        set.region(0, 0, set.image_size.x, set.image_size.y).data
+    }
+
+    #[test]
+    fn mirrors_when_the_axis_is_on_a_row() {
+        for height in [100, 101] {
+            let set = set(height, 0.);
+            assert_eq!(set.mirror(), Some(height / 2 * 2));
+            assert_eq!(set.iteration_map().data, brute_force(&set));
+        }
+    }
+
+    #[test]
+    fn mirrors_when_the_axis_is_between_rows() {
+        let set = set(100, 0.5 / 64.);
+        assert_eq!(set.mirror(), Some(99));
+        assert_eq!(set.iteration_map().data, brute_force(&set));
+    }
+
+    #[test]
+    fn mirrors_short_halves() {
+        for center in [-0.5, 0.5] {
+            let set = set(100, center);
+            assert!(set.mirror().is_some());
+            assert_eq!(set.iteration_map().data, brute_force(&set));
+        }
+    }
+
+    #[test]
+    fn rows_snap_to_the_axis() {
+        //The axis lies 0.3 rows above the middle of the view
+        let set = set(100, 0.3 / 64.);
+        assert_eq!(set.mirror(), Some(99));
+        assert_eq!(set.iteration_map().data, brute_force(&set));
+
+        let unsnapped = (0. - 50.) / 64. + 0.3 / 64.;
+        assert!((set.point(0., 0.).1 - unsnapped).abs() <= 0.25 / 64.);
+        for row in [0, 30, 49] {
+            assert!(
+                (set.point(0., row as f64).1 + set.point(0., (99 - row) as f64).1).abs() < 1e-15
+            );
+        }
+    }
+
+    #[test]
+    fn no_mirror_without_symmetry() {
+        assert_eq!(set(100, 0.).fractal(Fractal::BurningShip).mirror(), None);
+        assert_eq!(set(100, 0.).rotation(0.1).mirror(), None);
+        //The axis is outside of the view
+        assert_eq!(set(100, 1.).mirror(), None);
+    }
+
+    #[test]
+    fn strips_match_the_whole_map() {
+        for center in [-0.3, 0., 0.3] {
+            let set = set(100, center);
+            let mut strips = Strips::new(&set);
+
+            let mut data = Vec::new();
+            for y in (0..100).step_by(16) {
+                data.extend(strips.next(y, 16.min(100 - y)).data);
+            }
+            assert_eq!(data, brute_force(&set));
+            assert!(strips.kept.is_empty());
+        }
+    }
+
+    #[test]
+    fn strips_keep_at_most_one_strip() {
+        //The axis lies a third of the way down a tall view
+        let set = set(3000, 500. / 64.);
+        let mut strips = Strips::new(&set);
+
+        let (mut data, mut peak) = (Vec::new(), 0);
+        for y in (0..3000).step_by(16) {
+            data.extend(strips.next(y, 16.min(3000 - y)).data);
+            peak = peak.max(strips.kept.len());
+        }
+        assert_eq!(data, brute_force(&set));
+        assert!(peak > 0 && peak <= 16, "{peak}");
+        assert!(strips.kept.is_empty());
+    }
+
+    ///The same view moved by whole pixels
+    fn moved(set: &Mandelbrot, dx: isize, dy: isize) -> Mandelbrot {
+        let (dx, dy) = (dx as f64 / 64., dy as f64 / 64.);
+        let mut moved = set.clone();
+        moved.change_range(
+            (set.x_range.0 - dx, set.x_range.1 - dx),
+            (set.y_range.0 - dy, set.y_range.1 - dy),
+        );
+        moved
+    }
+
+    #[test]
+    fn pan_matches_a_full_render() {
+        let set = set(100, 0.2);
+        for (dx, dy) in [(0, 0), (13, 0), (0, -70), (-5, 21), (100, 3), (200, 200)] {
+            let mut iterations = set.iteration_map();
+            let moved = moved(&set, dx, dy);
+
+            assert!(moved.pan(&mut iterations, dx, dy, &CancelToken::new()));
+            assert_eq!(iterations.data, brute_force(&moved), "moved by {dx}, {dy}");
+        }
+    }
+
+    #[test]
+    fn pan_stops_when_cancelled() {
+        let set = set(100, 0.2);
+        let mut iterations = set.iteration_map();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+
+        assert!(!moved(&set, 4, 4).pan(&mut iterations, 4, 4, &cancel));
+    }
+
+    #[test]
+    fn regions_only_mirror_inside() {
+        let set = set(100, 0.);
+        let part = set.iteration_region(30, 20, 50, 70);
+        assert_eq!(part.data, set.region(30, 20, 50, 70).data);
+    }
 }
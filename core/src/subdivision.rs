@@ -0,0 +1,161 @@
+use rayon::prelude::*;
+
+use crate::{ColorMap, Mandelbrot};
+
+///Size of the square tiles the image gets split into before subdividing
+const TILE_SIZE: usize = 64;
+///Rectangles this small get calculated pixel by pixel
+const MIN_SIZE: usize = 4;
+///Marks pixels that were not calculated yet
+const UNKNOWN: u64 = u64::MAX;
+
+///Part of the image that is subdivided on its own
+struct Tile<'a> {
+    set: &'a Mandelbrot,
+    ///Position of the top left pixel in the image
+    x: usize,
+    y: usize,
+    iterations: ColorMap<u64>,
+}
+
+impl<'a> Tile<'a> {
+    fn new(set: &'a Mandelbrot, x: usize, y: usize, width: usize, height: usize) -> Self {
+        let mut iterations = ColorMap::new(width, height);
+        iterations.data.fill(UNKNOWN);
+
+        Self {
+            set,
+            x,
+            y,
+            iterations,
+        }
+    }
+
+    ///Iteration count of a pixel in the tile, only calculated once
+    fn get(&mut self, x: usize, y: usize) -> u64 {
+        if self.iterations[(x, y)] == UNKNOWN {
+            self.iterations[(x, y)] = self.set.get_pixel((self.x + x) as f64, (self.y + y) as f64);
+        }
+
+        self.iterations[(x, y)]
+    }
+
+    ///Fill or subdivide the rectangle between both corners, inclusive
+    fn subdivide(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) {
+        let first = self.get(x0, y0);
+        let mut uniform = true;
+
+        //Every border pixel is needed by the smaller rectangles anyways
+        for x in x0..=x1 {
+            uniform &= self.get(x, y0) == first;
+            uniform &= self.get(x, y1) == first;
+        }
+        for y in y0..=y1 {
+            uniform &= self.get(x0, y) == first;
+            uniform &= self.get(x1, y) == first;
+        }
+
+        //No pixels inside the border
+        if x1 - x0 < 2 || y1 - y0 < 2 {
+            return;
+        }
+
+        if uniform {
+            for y in y0 + 1..y1 {
+                for x in x0 + 1..x1 {
+                    self.iterations[(x, y)] = first;
+                }
+            }
+            return;
+        }
+
+        if x1 - x0 <= MIN_SIZE || y1 - y0 <= MIN_SIZE {
+            for y in y0 + 1..y1 {
+                for x in x0 + 1..x1 {
+                    self.get(x, y);
+                }
+            }
+            return;
+        }
+
+        //Split into quarters that share their middle border
+        let xm = (x0 + x1) / 2;
+        let ym = (y0 + y1) / 2;
+        self.subdivide(x0, y0, xm, ym);
+        self.subdivide(xm, y0, x1, ym);
+        self.subdivide(x0, ym, xm, y1);
+        self.subdivide(xm, ym, x1, y1);
+    }
+}
+
+///Calculate the iteration counts of a rectangle of pixels with Mariani–Silver subdivision
+pub fn render(set: &Mandelbrot, x: usize, y: usize, width: usize, height: usize) -> ColorMap<u64> {
+    let mut map = ColorMap::new(width, height);
+    if width == 0 || height == 0 {
+        return map;
+    }
+
+    //Every band of rows is calculated in parallel
+    map.data
+        .par_chunks_mut((width * TILE_SIZE).max(1))
+        .enumerate()
+        .for_each(|(band, rows)| {
+            let tile_y = y + band * TILE_SIZE;
+            let tile_height = rows.len() / width;
+
+            for tile_x in (0..width).step_by(TILE_SIZE) {
+                let tile_width = TILE_SIZE.min(width - tile_x);
+
+                let mut tile = Tile::new(set, x + tile_x, tile_y, tile_width, tile_height);
+                tile.subdivide(0, 0, tile_width - 1, tile_height - 1);
+
+                for (ty, row) in tile.iterations.data.chunks(tile_width).enumerate() {
+                    let start = ty * width + tile_x;
+                    rows[start..start + tile_width].copy_from_slice(row);
+                }
+            }
+        });
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Dim, Mandelbrot, Strategy};
+
+    fn set(x_range: (f64, f64), y_range: (f64, f64)) -> Mandelbrot {
+        Mandelbrot::from_range(Dim::new(160, 120), x_range, y_range)
+            .max_iterations(200)
+            .strategy(Strategy::Subdivision)
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        assert_eq!(set((-2., 0.47), (-1.12, 0.73)).verify_strategy(), vec![]);
+    }
+
+    #[test]
+    fn axis_between_rows_is_no_mismatch() {
+        //The real axis falls between sample rows, only the strategy gets compared
+        assert_eq!(set((-2., 0.47), (-1.1, 0.83)).verify_strategy(), vec![]);
+    }
+
+    #[test]
+    fn region_matches_the_whole_map() {
+        let set = set((-0.8, -0.7), (0.05, 0.125));
+        let whole = set.region(0, 0, 160, 120);
+
+        let part = set.region(70, 30, 64, 50);
+        assert_eq!(part.data, whole.crop(70, 30, 64, 50).data);
+    }
+
+    #[test]
+    fn empty_regions_are_empty() {
+        let set = set((-2., 0.47), (-1.12, 1.12));
+        for (width, height) in [(0, 10), (10, 0), (0, 0)] {
+            let region = set.region(5, 5, width, height);
+            assert_eq!((region.width, region.height), (width, height));
+            assert!(region.data.is_empty());
+        }
+    }
+}
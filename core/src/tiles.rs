@@ -0,0 +1,371 @@
+//!Tile pyramids for web viewers like OpenSeadragon and Leaflet.
+//!
+//!Every level is rendered at its own resolution instead of downscaling the full image.
+
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::dither;
+use crate::hdr::ToneMapper;
+use crate::{png_crate, ColorMap, Dim, Mandelbrot};
+
+///How the tiles are named and cut
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    ///Deep Zoom, a `.dzi` file next to `<name>_files/<level>/<column>_<row>.png`.
+    ///Tiles on the right and bottom edge are cut off at the image border.
+    DeepZoom,
+    ///`<z>/<x>/<y>.png`, level 0 is a single tile and every tile has the full size
+    Xyz,
+}
+
+///Smallest `n` with `2^n >= value`
+fn ceil_log2(value: usize) -> u32 {
+    usize::BITS - (value.max(1) - 1).leading_zeros()
+}
+
+///Renders the view of a set as tiles on multiple levels, the image size of the set is the size of the highest level
+pub struct Pyramid {
+    pub set: Mandelbrot,
+    ///Size of a tile without the overlap
+    pub tile_size: usize,
+    ///Pixels every tile shares with its neighbors on each side
+    pub overlap: usize,
+    pub layout: Layout,
+    ///Exposure, tone mapping and dithering of the colors
+    pub tone_mapper: ToneMapper,
+}
+
+impl Pyramid {
+    pub fn new(set: Mandelbrot) -> Self {
+        Self {
+            set,
+            tile_size: 256,
+            overlap: 0,
+            layout: Layout::DeepZoom,
+            tone_mapper: ToneMapper::default(),
+        }
+    }
+
+    pub fn tile_size(mut self, size: usize) -> Self {
+        self.tile_size = size.max(1);
+        self
+    }
+
+    pub fn overlap(mut self, overlap: usize) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn tone_mapper(mut self, tone_mapper: ToneMapper) -> Self {
+        self.tone_mapper = tone_mapper;
+        self
+    }
+
+    ///Highest level, where one pixel is a pixel of the full image
+    pub fn max_level(&self) -> u32 {
+        let largest = self.set.image_size.x.max(self.set.image_size.y);
+
+        match self.layout {
+            //Level 0 is a single pixel
+            Layout::DeepZoom => ceil_log2(largest),
+            //Level 0 is a single tile
+            Layout::Xyz => ceil_log2(largest.div_ceil(self.tile_size)),
+        }
+    }
+
+    ///Full image pixels per pixel of a level
+    fn factor(&self, level: u32) -> usize {
+        1 << (self.max_level() - level.min(self.max_level()))
+    }
+
+    ///Size of the image on a level
+    pub fn level_size(&self, level: u32) -> Dim<usize> {
+        let factor = self.factor(level);
+        Dim::new(
+            self.set.image_size.x.div_ceil(factor),
+            self.set.image_size.y.div_ceil(factor),
+        )
+    }
+
+    ///Columns and rows of tiles on a level
+    pub fn tile_count(&self, level: u32) -> Dim<usize> {
+        let size = self.level_size(level);
+        Dim::new(
+            size.x.div_ceil(self.tile_size),
+            size.y.div_ceil(self.tile_size),
+        )
+    }
+
+    ///Pixels rendered on a level, Xyz tiles can reach past the image as they are never cut off
+    fn rendered_size(&self, level: u32) -> Dim<usize> {
+        match self.layout {
+            Layout::DeepZoom => self.level_size(level),
+            Layout::Xyz => {
+                let count = self.tile_count(level);
+                Dim::new(count.x * self.tile_size, count.y * self.tile_size)
+            }
+        }
+    }
+
+    ///Corner and size of the area level 0 covers, every following level doubles its pixels per side
+    fn extent(&self) -> ((f64, f64), (f64, f64)) {
+        let (x_range, y_range) = (self.set.x_range, self.set.y_range);
+        let (size, factor) = (self.rendered_size(0), self.factor(0) as f64);
+
+        //Size of a full image pixel
+        let dx = (x_range.1 - x_range.0) / self.set.image_size.x as f64;
+        let dy = (y_range.1 - y_range.0) / self.set.image_size.y as f64;
+
+        (
+            (x_range.0, y_range.0),
+            (size.x as f64 * factor * dx, size.y as f64 * factor * dy),
+        )
+    }
+
+    ///Set with one pixel for every pixel of a level, it covers at least the whole level.
+    ///
+    ///Every pixel samples its center, so the levels sample the middle of the pixels they merge.
+    fn level_set(&self, level: u32) -> Mandelbrot {
+        let size = self.rendered_size(level);
+        let zero = self.rendered_size(0);
+        let (corner, extent) = self.extent();
+
+        //Sets sample pixel `x` at `x - width / 2` pixels from their center, the left edge of the pixel
+        //for even widths and its center for odd ones. The range is moved so that every size samples centers.
+        let range = |corner: f64, extent: f64, zero: usize, pixels: usize| {
+            let step = extent / (zero << level) as f64;
+            let start = corner + (0.5 + (pixels / 2) as f64 - pixels as f64 / 2.) * step;
+            (start, start + pixels as f64 * step)
+        };
+
+        let mut set = self.set.clone();
+        set.image_size = size;
+        set.change_range(
+            range(corner.0, extent.0, zero.x, size.x),
+            range(corner.1, extent.1, zero.y, size.y),
+        );
+        set
+    }
+
+    ///Colors of a single tile including its overlap
+    pub fn tile(&self, level: u32, column: usize, row: usize) -> ColorMap<[u8; 3]> {
+        self.render_tile(&self.level_set(level), column, row)
+    }
+
+    fn render_tile(&self, set: &Mandelbrot, column: usize, row: usize) -> ColorMap<[u8; 3]> {
+        let size = set.image_size;
+
+        let x = (column * self.tile_size).saturating_sub(self.overlap);
+        let y = (row * self.tile_size).saturating_sub(self.overlap);
+        let x_end = ((column + 1) * self.tile_size + self.overlap).min(size.x);
+        let y_end = ((row + 1) * self.tile_size + self.overlap).min(size.y);
+
+        let (width, height) = (x_end.saturating_sub(x), y_end.saturating_sub(y));
+
+        if self.tone_mapper == ToneMapper::default() {
+            return set
+                .iteration_region(x, y, width, height)
+                .map(|&iter| set.color(iter));
+        }
+
+        //Dither whole blocks, so neighboring tiles and the overlaps match
+        let ((block_x, block_y), (block_width, block_height)) =
+            dither::block_region((x, y), (width, height), (size.x, size.y));
+        let linear = set
+            .iteration_region(block_x, block_y, block_width, block_height)
+            .map(|&iter| set.color_linear(iter));
+
+        self.tone_mapper.map_region(&linear, block_x, block_y).crop(
+            x - block_x,
+            y - block_y,
+            width,
+            height,
+        )
+    }
+
+    ///Deep Zoom descriptor of the image
+    pub fn dzi(&self) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"png\" Overlap=\"{}\" TileSize=\"{}\">\n\
+             \x20 <Size Width=\"{}\" Height=\"{}\"/>\n\
+             </Image>\n",
+            self.overlap, self.tile_size, self.set.image_size.x, self.set.image_size.y
+        )
+    }
+
+    ///Render every tile of every level, returns how many tiles were written.
+    ///
+    ///For Deep Zoom `path` is the `.dzi` file, for Xyz it is the directory of the levels.
+    pub fn export(&self, path: &Path, mut progress: impl FnMut(u32)) -> io::Result<usize> {
+        let root = match self.layout {
+            Layout::DeepZoom => {
+                let mut w = BufWriter::new(File::create(path)?);
+                w.write_all(self.dzi().as_bytes())?;
+                w.flush()?;
+
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                path.with_file_name(format!("{stem}_files"))
+            }
+            Layout::Xyz => path.to_path_buf(),
+        };
+
+        let mut written = 0;
+        for level in 0..=self.max_level() {
+            let set = self.level_set(level);
+            let count = self.tile_count(level);
+
+            let level_dir = root.join(level.to_string());
+            fs::create_dir_all(&level_dir)?;
+            if self.layout == Layout::Xyz {
+                for column in 0..count.x {
+                    fs::create_dir_all(level_dir.join(column.to_string()))?;
+                }
+            }
+
+            (0..count.x * count.y).into_par_iter().try_for_each(|i| {
+                let (column, row) = (i % count.x, i / count.x);
+                let file = match self.layout {
+                    Layout::DeepZoom => level_dir.join(format!("{column}_{row}.png")),
+                    Layout::Xyz => level_dir.join(format!("{column}/{row}.png")),
+                };
+
+                png_crate::save(&file, &self.render_tile(&set, column, row))
+            })?;
+
+            written += count.x * count.y;
+            progress(level);
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    //64 pixels per unit, so every level hits the same points
+    fn set() -> Mandelbrot {
+        Mandelbrot::from_range(
+            Dim::new(100, 60),
+            (-2., -2. + 100. / 64.),
+            (-30. / 64., 30. / 64.),
+        )
+        .max_iterations(100)
+    }
+
+    #[test]
+    fn levels_halve_the_size() {
+        assert_eq!(
+            (ceil_log2(0), ceil_log2(1), ceil_log2(2), ceil_log2(100)),
+            (0, 0, 1, 7)
+        );
+
+        let pyramid = Pyramid::new(set()).tile_size(32);
+        assert_eq!(pyramid.max_level(), 7);
+        assert_eq!(pyramid.level_size(7), Dim::new(100, 60));
+        assert_eq!(pyramid.level_size(6), Dim::new(50, 30));
+        assert_eq!(pyramid.level_size(5), Dim::new(25, 15));
+        assert_eq!(pyramid.level_size(0), Dim::new(1, 1));
+        assert_eq!(pyramid.tile_count(7), Dim::new(4, 2));
+        assert_eq!(pyramid.tile_count(0), Dim::new(1, 1));
+
+        let xyz = pyramid.layout(Layout::Xyz);
+        assert_eq!(xyz.max_level(), 2);
+        assert_eq!(xyz.tile_count(2), Dim::new(4, 2));
+        assert_eq!(xyz.tile_count(0), Dim::new(1, 1));
+    }
+
+    #[test]
+    fn levels_sample_pixel_centers() {
+        for layout in [Layout::DeepZoom, Layout::Xyz] {
+            let pyramid = Pyramid::new(set()).tile_size(32).layout(layout);
+
+            for level in 0..=pyramid.max_level() {
+                let set = pyramid.level_set(level);
+                let step = pyramid.factor(level) as f64 / 64.;
+
+                for (x, y) in [(0, 0), (1, 2), (set.image_size.x - 1, set.image_size.y - 1)] {
+                    let (real, imaginary) = set.point(x as f64, y as f64);
+                    let expected = (
+                        -2. + (x as f64 + 0.5) * step,
+                        -30. / 64. + (y as f64 + 0.5) * step,
+                    );
+
+                    assert!((real - expected.0).abs() < 1e-12, "{layout:?} {level}");
+                    //Rows may move by a quarter pixel to meet the real axis
+                    assert!(
+                        (imaginary - expected.1).abs() <= step / 4. + 1e-12,
+                        "{layout:?} {level}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_are_cut_from_the_full_image() {
+        //The full image sampled at its pixel centers
+        let mut set = set();
+        let half = 0.5 / 64.;
+        set.change_range(
+            (set.x_range.0 + half, set.x_range.1 + half),
+            (set.y_range.0 + half, set.y_range.1 + half),
+        );
+        let full = set.iteration_map().map(|&iter| set.color(iter));
+        let set = self::set();
+        let pyramid = Pyramid::new(set).tile_size(32).overlap(2);
+
+        let tile = pyramid.tile(7, 1, 1);
+        assert_eq!((tile.width, tile.height), (36, 30));
+        assert_eq!(tile.data, full.crop(30, 30, 36, 30).data);
+
+        let corner = pyramid.tile(7, 3, 0);
+        assert_eq!((corner.width, corner.height), (6, 34));
+        assert_eq!(corner.data, full.crop(94, 0, 6, 34).data);
+    }
+
+    #[test]
+    fn xyz_tiles_have_the_full_size() {
+        let pyramid = Pyramid::new(set()).tile_size(32).layout(Layout::Xyz);
+        for level in 0..=pyramid.max_level() {
+            let tile = pyramid.tile(level, 0, 0);
+            assert_eq!((tile.width, tile.height), (32, 32));
+        }
+    }
+
+    #[test]
+    fn exports_every_tile() {
+        let pyramid = Pyramid::new(set()).tile_size(64).overlap(1);
+        let dzi = pyramid.dzi();
+        assert!(dzi.contains("Overlap=\"1\" TileSize=\"64\""));
+        assert!(dzi.contains("<Size Width=\"100\" Height=\"60\"/>"));
+
+        let dir = TempDir::new("tiles");
+        let mut levels = Vec::new();
+        let written = pyramid
+            .export(&dir.join("set.dzi"), |level| levels.push(level))
+            .unwrap();
+
+        //Two tiles on the highest level and one on each of the others
+        assert_eq!(written, 9);
+        assert_eq!(levels, (0..=7).collect::<Vec<_>>());
+        assert_eq!(fs::read_to_string(dir.join("set.dzi")).unwrap(), dzi);
+        let tile = png_crate::load(&dir.join("set_files/7/1_0.png")).unwrap();
+        assert_eq!((tile.width, tile.height), (37, 60));
+
+        let xyz = pyramid.layout(Layout::Xyz);
+        assert_eq!(xyz.export(&dir.join("xyz"), |_| ()).unwrap(), 3);
+        assert!(dir.join("xyz/1/1/0.png").exists());
+    }
+}
@@ -0,0 +1,278 @@
+//!YUV4MPEG2 video stream, raw frames that video encoders like ffmpeg and x264 read directly.
+//!
+//!The stream has no field for the color matrix, the encoder has to be told which one was used,
+//!like `-colorspace bt709` for ffmpeg. Values use the limited range of 16 to 235.
+
+use rayon::prelude::*;
+use std::io::{self, Write};
+
+use crate::ColorMap;
+
+///Matrix of the RGB to YUV conversion
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Matrix {
+    ///Standard definition video
+    Bt601,
+    ///High definition video
+    Bt709,
+}
+
+impl Matrix {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bt601" => Some(Matrix::Bt601),
+            "bt709" => Some(Matrix::Bt709),
+            _ => None,
+        }
+    }
+
+    ///Weights of red and blue in the luma
+    fn weights(self) -> (f32, f32) {
+        match self {
+            Matrix::Bt601 => (0.299, 0.114),
+            Matrix::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+///Resolution of the chroma planes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subsampling {
+    ///One chroma sample per 2x2 pixels, what most encoders expect
+    Yuv420,
+    ///One chroma sample per pixel
+    Yuv444,
+}
+
+impl Subsampling {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "420" => Some(Subsampling::Yuv420),
+            "444" => Some(Subsampling::Yuv444),
+            _ => None,
+        }
+    }
+
+    ///Pixels per chroma sample in each direction
+    fn factor(self) -> usize {
+        match self {
+            Subsampling::Yuv420 => 2,
+            Subsampling::Yuv444 => 1,
+        }
+    }
+}
+
+///Writes frames of the same size into a YUV4MPEG2 stream
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    pub width: usize,
+    pub height: usize,
+    ///Frame rate as numerator and denominator
+    pub fps: (u32, u32),
+    pub matrix: Matrix,
+    pub subsampling: Subsampling,
+    ///Whether the stream header was written, it is written before the first frame
+    started: bool,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(writer: W, width: usize, height: usize) -> Self {
+        Self {
+            writer,
+            width,
+            height,
+            fps: (30, 1),
+            matrix: Matrix::Bt709,
+            subsampling: Subsampling::Yuv420,
+            started: false,
+        }
+    }
+
+    pub fn fps(mut self, numerator: u32, denominator: u32) -> Self {
+        self.fps = (numerator, denominator.max(1));
+        self
+    }
+
+    pub fn matrix(mut self, matrix: Matrix) -> Self {
+        self.matrix = matrix;
+        self
+    }
+
+    pub fn subsampling(mut self, subsampling: Subsampling) -> Self {
+        self.subsampling = subsampling;
+        self
+    }
+
+    fn header(&self) -> String {
+        let colorspace = match self.subsampling {
+            Subsampling::Yuv420 => "C420jpeg XYSCSS=420JPEG",
+            Subsampling::Yuv444 => "C444 XYSCSS=444",
+        };
+        format!(
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 {colorspace} XCOLORRANGE=LIMITED\n",
+            self.width, self.height, self.fps.0, self.fps.1
+        )
+    }
+
+    ///Convert a frame into its Y, U and V planes
+    fn planes(&self, map: &ColorMap<[u8; 3]>) -> Vec<u8> {
+        let (width, height) = (self.width, self.height);
+        let factor = self.subsampling.factor();
+        let (chroma_width, chroma_height) = (width.div_ceil(factor), height.div_ceil(factor));
+
+        let (kr, kb) = self.matrix.weights();
+        let kg = 1. - kr - kb;
+        let luma = |[r, g, b]: [u8; 3]| kr * r as f32 + kg * g as f32 + kb * b as f32;
+
+        let mut data = vec![0; width * height + 2 * chroma_width * chroma_height];
+        let (y_plane, chroma) = data.split_at_mut(width * height);
+        let (u_plane, v_plane) = chroma.split_at_mut(chroma_width * chroma_height);
+
+        y_plane
+            .par_chunks_mut(width)
+            .zip(map.data.par_chunks(map.width))
+            .for_each(|(row, colors)| {
+                for (y, &color) in row.iter_mut().zip(colors) {
+                    *y = (16. + luma(color) * (219. / 255.)).round() as u8;
+                }
+            });
+
+        u_plane
+            .par_chunks_mut(chroma_width)
+            .zip(v_plane.par_chunks_mut(chroma_width))
+            .enumerate()
+            .for_each(|(cy, (u_row, v_row))| {
+                for cx in 0..chroma_width {
+                    //Average the color differences of the pixels that share the sample
+                    let (mut pb, mut pr, mut count) = (0., 0., 0.);
+                    for py in cy * factor..((cy + 1) * factor).min(height) {
+                        for px in cx * factor..((cx + 1) * factor).min(width) {
+                            let color = map[(px, py)];
+                            let y = luma(color);
+                            pb += (color[2] as f32 - y) / (2. * (1. - kb));
+                            pr += (color[0] as f32 - y) / (2. * (1. - kr));
+                            count += 1.;
+                        }
+                    }
+
+                    u_row[cx] = (128. + pb / count * (224. / 255.)).round() as u8;
+                    v_row[cx] = (128. + pr / count * (224. / 255.)).round() as u8;
+                }
+            });
+
+        data
+    }
+
+    ///Append a frame, it must have the size of the stream
+    pub fn write_frame(&mut self, map: &ColorMap<[u8; 3]>) -> io::Result<()> {
+        if map.width != self.width || map.height != self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Frame is {} x {}, the stream is {} x {}",
+                    map.width, map.height, self.width, self.height
+                ),
+            ));
+        }
+
+        if !self.started {
+            self.writer.write_all(self.header().as_bytes())?;
+            self.started = true;
+        }
+
+        let planes = self.planes(map);
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&planes)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zoom_sequence::ZoomSequence;
+    use crate::{CancelToken, Dim, Mandelbrot};
+
+    fn uniform(width: usize, height: usize, color: [u8; 3]) -> ColorMap<[u8; 3]> {
+        ColorMap {
+            width,
+            height,
+            data: vec![color; width * height],
+        }
+    }
+
+    #[test]
+    fn header_describes_the_stream() {
+        let mut writer = Y4mWriter::new(Vec::new(), 5, 3).fps(25, 0);
+        writer.write_frame(&uniform(5, 3, [0; 3])).unwrap();
+        let stream = writer.into_inner();
+
+        let header = "YUV4MPEG2 W5 H3 F25:1 Ip A1:1 C420jpeg XYSCSS=420JPEG XCOLORRANGE=LIMITED\n";
+        assert!(stream.starts_with(header.as_bytes()));
+        assert_eq!(&stream[header.len()..header.len() + 6], b"FRAME\n");
+    }
+
+    #[test]
+    fn odd_sizes_round_up_the_chroma() {
+        for (subsampling, chroma) in [(Subsampling::Yuv420, 3 * 2), (Subsampling::Yuv444, 5 * 3)] {
+            let mut writer = Y4mWriter::new(Vec::new(), 5, 3).subsampling(subsampling);
+            writer.write_frame(&uniform(5, 3, [0; 3])).unwrap();
+            writer.write_frame(&uniform(5, 3, [0; 3])).unwrap();
+
+            let header = writer.header().len();
+            let frame = 6 + 5 * 3 + 2 * chroma;
+            assert_eq!(writer.into_inner().len(), header + 2 * frame);
+        }
+    }
+
+    #[test]
+    fn colors_use_the_limited_range() {
+        let writer = Y4mWriter::new(Vec::new(), 2, 2).matrix(Matrix::Bt601);
+        assert_eq!(
+            writer.planes(&uniform(2, 2, [0; 3])),
+            [16, 16, 16, 16, 128, 128]
+        );
+        assert_eq!(
+            writer.planes(&uniform(2, 2, [255; 3])),
+            [235, 235, 235, 235, 128, 128]
+        );
+
+        //Pure red has the largest red difference
+        let red = writer.planes(&uniform(2, 2, [255, 0, 0]));
+        assert_eq!((red[0], red[4], red[5]), (81, 90, 240));
+        let red = writer
+            .matrix(Matrix::Bt709)
+            .planes(&uniform(2, 2, [255, 0, 0]));
+        assert_eq!((red[0], red[5]), (63, 240));
+    }
+
+    #[test]
+    fn frames_need_the_stream_size() {
+        let mut writer = Y4mWriter::new(Vec::new(), 4, 4);
+        assert!(writer.write_frame(&uniform(4, 3, [0; 3])).is_err());
+        assert!(writer.into_inner().is_empty());
+    }
+
+    #[test]
+    fn animations_stream_every_frame() {
+        let set = Mandelbrot::from_range(Dim::new(8, 6), (-2., 0.5), (-1., 1.)).max_iterations(50);
+        let sequence = ZoomSequence::new(set, (-0.75, 0.1), 1., 4., 7);
+
+        let mut writer = Y4mWriter::new(Vec::new(), 8, 6);
+        let written = sequence
+            .stream(&mut writer, &CancelToken::new(), |_| ())
+            .unwrap();
+        let stream = writer.into_inner();
+
+        assert_eq!(written, 7);
+        let frames = stream.windows(6).filter(|w| w == b"FRAME\n").count();
+        assert_eq!(frames, 7);
+    }
+}
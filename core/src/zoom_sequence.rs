@@ -0,0 +1,454 @@
+//!Zoom videos from a few keyframes, like the zoom sequences of Kalles Fraktaler.
+//!
+//!One keyframe is rendered for every halving of the zoom, starting at the deep target and
+//!going outward. The frames in between are blended from the two keyframes around them,
+//!scaled to the zoom of the frame.
+
+use rayon::prelude::*;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use crate::animation::{Animation, Keyframe};
+use crate::location::Location;
+use crate::y4m::Y4mWriter;
+use crate::{color, parallel_png, png_crate, CancelToken, ColorMap, Mandelbrot};
+
+///Share of the smaller image side used to fade the inner keyframe into the outer one
+const FEATHER: f64 = 0.1;
+
+fn smoothstep(t: f64) -> f64 {
+    let t = t.clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+///Bilinear sample in linear light, positions outside the map are clamped to its border
+fn sample(map: &ColorMap<[u8; 3]>, x: f64, y: f64) -> [f32; 3] {
+    let x = x.clamp(0., (map.width - 1) as f64);
+    let y = y.clamp(0., (map.height - 1) as f64);
+
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(map.width - 1), (y0 + 1).min(map.height - 1));
+    let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+
+    let (a, b) = (
+        color::to_linear(map[(x0, y0)]),
+        color::to_linear(map[(x1, y0)]),
+    );
+    let (c, d) = (
+        color::to_linear(map[(x0, y1)]),
+        color::to_linear(map[(x1, y1)]),
+    );
+
+    [0, 1, 2].map(|i| {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        top + (bottom - top) * fy
+    })
+}
+
+///Zoom into a single point with one keyframe per halving of the zoom
+#[derive(Clone)]
+pub struct ZoomSequence {
+    ///Iterations, fractal, colors and frame size
+    pub set: Mandelbrot,
+    pub center: (f64, f64),
+    ///Zoom of the first frame, relative to the whole set
+    pub start_zoom: f64,
+    ///Zoom of the last frame, the first keyframe
+    pub end_zoom: f64,
+    pub frames: usize,
+}
+
+impl ZoomSequence {
+    pub fn new(
+        set: Mandelbrot,
+        center: (f64, f64),
+        start_zoom: f64,
+        end_zoom: f64,
+        frames: usize,
+    ) -> Self {
+        Self {
+            set,
+            center,
+            start_zoom,
+            end_zoom,
+            frames,
+        }
+    }
+
+    ///Number of keyframes, the last one is zoomed out at least as far as the first frame
+    pub fn keyframes(&self) -> usize {
+        ((self.end_zoom / self.start_zoom).log2().ceil() as usize).max(1) + 1
+    }
+
+    ///Zoom of a keyframe, keyframe 0 is the deepest
+    pub fn keyframe_zoom(&self, keyframe: usize) -> f64 {
+        self.end_zoom / 2_f64.powi(keyframe as i32)
+    }
+
+    ///Render settings of a keyframe
+    pub fn keyframe_set(&self, keyframe: usize) -> Mandelbrot {
+        let view = Keyframe::new(self.center, self.keyframe_zoom(keyframe));
+        let (x_range, y_range) = view.ranges(self.set.image_size);
+
+        let mut set = self.set.clone();
+        set.change_range(x_range, y_range);
+        set
+    }
+
+    pub fn render_keyframe(&self, keyframe: usize) -> ColorMap<[u8; 3]> {
+        let set = self.keyframe_set(keyframe);
+        set.iteration_map().map(|&iter| set.color(iter))
+    }
+
+    ///File of a keyframe inside the output directory
+    pub fn keyframe_path(dir: &Path, keyframe: usize) -> PathBuf {
+        dir.join(format!("keyframe_{keyframe:05}.png"))
+    }
+
+    ///File of the sequence parameters inside the output directory
+    pub fn parameters_path(dir: &Path) -> PathBuf {
+        dir.join("sequence.txt")
+    }
+
+    ///Every parameter that changes the keyframes or frames, one `name: value` per line
+    pub fn parameters(&self) -> String {
+        let set = &self.set;
+        [
+            ("Width", set.image_size.x.to_string()),
+            ("Height", set.image_size.y.to_string()),
+            ("Fractal", set.fractal.name().to_string()),
+            ("Center real", self.center.0.to_string()),
+            ("Center imaginary", self.center.1.to_string()),
+            ("Start zoom", self.start_zoom.to_string()),
+            ("End zoom", self.end_zoom.to_string()),
+            ("Frames", self.frames.to_string()),
+            ("Rotation", set.rotation.to_string()),
+            ("Iterations", set.max_iterations.to_string()),
+            ("Escape radius", set.radius.to_string()),
+            ("Palette", set.palette.name().to_string()),
+            ("Palette offset", set.palette_offset.to_string()),
+        ]
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}\n"))
+        .collect()
+    }
+
+    ///Make sure the directory holds nothing but this sequence, writes the parameters into a new one
+    fn claim(&self, dir: &Path) -> io::Result<()> {
+        let path = Self::parameters_path(dir);
+        let different = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} holds frames of a different zoom sequence, use an empty directory",
+                    dir.display()
+                ),
+            )
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(parameters) if parameters == self.parameters() => Ok(()),
+            Ok(_) => Err(different()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                //Frames without parameters could be from anything
+                if Self::keyframe_path(dir, 0).exists() || Animation::frame_path(dir, 0).exists() {
+                    return Err(different());
+                }
+                fs::write(&path, self.parameters())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    ///Zoom of a frame, the frames zoom in at a constant speed
+    pub fn frame_zoom(&self, frame: usize) -> f64 {
+        let t = frame as f64 / (self.frames.max(2) - 1) as f64;
+        self.start_zoom * (self.end_zoom / self.start_zoom).powf(t)
+    }
+
+    ///Outer keyframe of a frame, the inner one is the next deeper keyframe
+    pub fn outer_keyframe(&self, frame: usize) -> usize {
+        let depth = (self.end_zoom / self.frame_zoom(frame)).log2().max(0.);
+        ((depth.floor() as usize) + 1).min(self.keyframes() - 1)
+    }
+
+    ///Blend a frame from the keyframes around it.
+    ///
+    ///The inner keyframe covers the middle of the frame with more detail, its border fades
+    ///into the outer keyframe. Right before the outer keyframe is reached, the inner one fades
+    ///out completely, so the frames run smoothly into the next pair of keyframes.
+    pub fn frame(
+        &self,
+        outer: &ColorMap<[u8; 3]>,
+        inner: &ColorMap<[u8; 3]>,
+        outer_keyframe: usize,
+        zoom: f64,
+    ) -> ColorMap<[u8; 3]> {
+        let (width, height) = (self.set.image_size.x, self.set.image_size.y);
+        //Pixel of the center, like in `Mandelbrot::get_pixel`
+        let (half_width, half_height) = ((width / 2) as f64, (height / 2) as f64);
+
+        //Keyframe pixels per frame pixel, from 1 to 2 for the inner keyframe
+        let inner_scale = self.keyframe_zoom(outer_keyframe - 1) / zoom;
+        let outer_scale = self.keyframe_zoom(outer_keyframe) / zoom;
+
+        let feather = FEATHER * (inner_scale - 1.) * width.min(height) as f64;
+        let fade_out = ((2. - inner_scale) * 4.).clamp(0., 1.);
+        //Distance of the 2x2 samples, grows with the shrinking so keyframe frames stay sharp
+        let spread = (inner_scale - 1.) / 4.;
+
+        let mut map = ColorMap::new(width, height);
+        map.data
+            .par_chunks_mut(width.max(1))
+            .enumerate()
+            .for_each(|(py, row)| {
+                for (px, color) in row.iter_mut().enumerate() {
+                    //Position relative to the center in frame pixels
+                    let (dx, dy) = (px as f64 - half_width, py as f64 - half_height);
+
+                    let outer_color = sample(
+                        outer,
+                        dx * outer_scale + half_width,
+                        dy * outer_scale + half_height,
+                    );
+
+                    //Distance to the border of the inner keyframe
+                    let distance = (half_width / inner_scale - dx.abs())
+                        .min(half_height / inner_scale - dy.abs());
+                    let weight = if distance < 0. {
+                        0.
+                    } else if feather > 0. {
+                        smoothstep(distance / feather) * fade_out
+                    } else {
+                        fade_out
+                    };
+
+                    if weight <= 0. {
+                        *color = color::from_linear(outer_color);
+                        continue;
+                    }
+
+                    //The inner keyframe is shrunk, average 2x2 samples against aliasing
+                    let mut inner_color = [0.; 3];
+                    for (ox, oy) in [(-1., -1.), (1., -1.), (-1., 1.), (1., 1.)] {
+                        let s = sample(
+                            inner,
+                            (dx + ox * spread) * inner_scale + half_width,
+                            (dy + oy * spread) * inner_scale + half_height,
+                        );
+                        for i in 0..3 {
+                            inner_color[i] += s[i] / 4.;
+                        }
+                    }
+
+                    let weight = weight as f32;
+                    *color = color::from_linear(
+                        [0, 1, 2]
+                            .map(|i| outer_color[i] + (inner_color[i] - outer_color[i]) * weight),
+                    );
+                }
+            });
+
+        map
+    }
+
+    ///Render the keyframes from the deepest outward, then blend the frames into the directory.
+    ///
+    ///Keyframes and frames that already exist are reused, so an interrupted render continues where it stopped.
+    ///The parameters are kept in the directory, resuming a different sequence there is an error.
+    ///`progress` gets the number of finished keyframes and frames, returns how many frames were written.
+    pub fn render(
+        &self,
+        dir: &Path,
+        cancel: &CancelToken,
+        mut progress: impl FnMut(usize),
+    ) -> io::Result<usize> {
+        fs::create_dir_all(dir)?;
+        self.claim(dir)?;
+        let keyframes = self.keyframes();
+
+        for keyframe in 0..keyframes {
+            if cancel.is_cancelled() {
+                return Ok(0);
+            }
+
+            let path = Self::keyframe_path(dir, keyframe);
+            if !path.exists() {
+                let temporary = path.with_extension("png.part");
+                let location = Location::of(&self.keyframe_set(keyframe));
+                parallel_png::save(&temporary, &self.render_keyframe(keyframe), Some(&location))?;
+                fs::rename(&temporary, &path)?;
+            }
+            progress(keyframe + 1);
+        }
+
+        //The two keyframes of the current frame, they change every few frames
+        let mut loaded = None;
+        let (mut outer, mut inner) = (ColorMap::new(0, 0), ColorMap::new(0, 0));
+        let mut written = 0;
+
+        for frame in 0..self.frames {
+            if cancel.is_cancelled() {
+                break;
+            }
+
+            let path = Animation::frame_path(dir, frame);
+            if !path.exists() {
+                let outer_keyframe = self.outer_keyframe(frame);
+                if loaded != Some(outer_keyframe) {
+                    outer = png_crate::load(&Self::keyframe_path(dir, outer_keyframe))?;
+                    inner = png_crate::load(&Self::keyframe_path(dir, outer_keyframe - 1))?;
+                    loaded = Some(outer_keyframe);
+                }
+
+                let zoom = self.frame_zoom(frame);
+                let colors = self.frame(&outer, &inner, outer_keyframe, zoom);
+                let temporary = path.with_extension("png.part");
+                let location = Location {
+                    center: self.center,
+                    zoom,
+                    ..Location::of(&self.keyframe_set(0))
+                };
+                parallel_png::save(&temporary, &colors, Some(&location))?;
+                fs::rename(&temporary, &path)?;
+                written += 1;
+            }
+            progress(keyframes + frame + 1);
+        }
+
+        Ok(written)
+    }
+
+    ///Render the frames into a video stream, returns how many frames were written.
+    ///
+    ///Only the two keyframes of the current frame are kept in memory, they are rendered
+    ///from the outermost inward as the frames reach them.
+    pub fn stream<W: Write>(
+        &self,
+        writer: &mut Y4mWriter<W>,
+        cancel: &CancelToken,
+        mut progress: impl FnMut(usize),
+    ) -> io::Result<usize> {
+        let mut loaded = None;
+        let (mut outer, mut inner) = (ColorMap::new(0, 0), ColorMap::new(0, 0));
+
+        for frame in 0..self.frames {
+            if cancel.is_cancelled() {
+                return Ok(frame);
+            }
+
+            let outer_keyframe = self.outer_keyframe(frame);
+            if loaded != Some(outer_keyframe) {
+                //Zooming in by one keyframe, the old inner keyframe becomes the outer one
+                outer = if loaded == Some(outer_keyframe + 1) {
+                    mem::replace(&mut inner, ColorMap::new(0, 0))
+                } else {
+                    self.render_keyframe(outer_keyframe)
+                };
+                inner = self.render_keyframe(outer_keyframe - 1);
+                loaded = Some(outer_keyframe);
+            }
+
+            let colors = self.frame(&outer, &inner, outer_keyframe, self.frame_zoom(frame));
+            writer.write_frame(&colors)?;
+            progress(frame + 1);
+        }
+
+        writer.flush()?;
+        Ok(self.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::Dim;
+
+    fn sequence(end_zoom: f64, frames: usize) -> ZoomSequence {
+        let set =
+            Mandelbrot::from_range(Dim::new(16, 12), (-2., 0.5), (-1., 1.)).max_iterations(50);
+        ZoomSequence::new(set, (-0.75, 0.1), 1., end_zoom, frames)
+    }
+
+    #[test]
+    fn one_keyframe_per_halving() {
+        assert_eq!(sequence(1., 10).keyframes(), 2);
+
+        let sequence = sequence(1000., 100);
+        assert_eq!(sequence.keyframes(), 11);
+        assert_eq!(sequence.keyframe_zoom(0), 1000.);
+        assert_eq!(sequence.keyframe_zoom(10), 1000. / 1024.);
+
+        assert_eq!(sequence.frame_zoom(0), 1.);
+        assert!((sequence.frame_zoom(99) - 1000.).abs() < 1e-9);
+        assert_eq!(sequence.outer_keyframe(0), 10);
+        assert_eq!(sequence.outer_keyframe(99), 1);
+    }
+
+    #[test]
+    fn keyframe_zooms_show_the_keyframes() {
+        let sequence = sequence(8., 10);
+        let (outer, inner) = (sequence.render_keyframe(2), sequence.render_keyframe(1));
+
+        //Right at the outer keyframe the inner one is faded out
+        let frame = sequence.frame(&outer, &inner, 2, sequence.keyframe_zoom(2));
+        assert_eq!(frame.data, outer.data);
+
+        //At the inner keyframe it covers the whole frame
+        let frame = sequence.frame(&outer, &inner, 2, sequence.keyframe_zoom(1));
+        assert_eq!(frame.data, inner.data);
+    }
+
+    #[test]
+    fn renders_resume_after_the_finished_frames() {
+        let temp = TempDir::new("zoom-sequence");
+        let dir = temp.path();
+        let sequence = sequence(4., 5);
+        let cancel = CancelToken::new();
+
+        let mut steps = Vec::new();
+        assert_eq!(
+            sequence
+                .render(dir, &cancel, |done| steps.push(done))
+                .unwrap(),
+            5
+        );
+        assert_eq!(steps, (1..=8).collect::<Vec<_>>());
+        assert!(ZoomSequence::keyframe_path(dir, 2).exists());
+
+        let first = png_crate::load(&Animation::frame_path(dir, 0)).unwrap();
+        assert_eq!((first.width, first.height), (16, 12));
+
+        fs::remove_file(Animation::frame_path(dir, 3)).unwrap();
+        assert_eq!(sequence.render(dir, &cancel, |_| ()).unwrap(), 1);
+
+        cancel.cancel();
+        assert_eq!(sequence.render(dir, &cancel, |_| ()).unwrap(), 0);
+    }
+
+    #[test]
+    fn refuses_to_resume_a_different_sequence() {
+        let temp = TempDir::new("zoom-sequence");
+        let dir = temp.path();
+        let cancel = CancelToken::new();
+
+        sequence(4., 5).render(dir, &cancel, |_| ()).unwrap();
+        let parameters = fs::read_to_string(ZoomSequence::parameters_path(dir)).unwrap();
+        assert!(parameters.contains("End zoom: 4\n"));
+
+        let mut deeper = sequence(4., 5);
+        deeper.set.max_iterations = 100;
+        for different in [sequence(8., 5), sequence(4., 6), deeper] {
+            let err = different.render(dir, &cancel, |_| ()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        //Frames from somewhere else
+        fs::remove_file(ZoomSequence::parameters_path(dir)).unwrap();
+        assert!(sequence(4., 5).render(dir, &cancel, |_| ()).is_err());
+    }
+}
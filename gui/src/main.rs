@@ -11,7 +11,7 @@ fn main() {
     eframe::run_native(
         "Mandelbrot",
         options,
-        Box::new(|_cc| Box::new(MyApp::default())),
+        Box::new(|cc| Box::new(MyApp::new(cc))),
     );
 }
 
@@ -20,10 +20,32 @@ struct MyApp {
     mandelbrot: Mandelbrot,
 }
 
+impl MyApp {
+    ///Start where the last run stopped when the state is remembered
+    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        #[cfg(feature = "serde")]
+        if let Some(mandelbrot) = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+        {
+            return Self { mandelbrot };
+        }
+        #[cfg(not(feature = "serde"))]
+        let _ = cc;
+
+        Self::default()
+    }
+}
+
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
             self.mandelbrot.ui(ui);
         });
     }
+
+    #[cfg(feature = "serde")]
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, eframe::APP_KEY, &self.mandelbrot);
+    }
 }
@@ -0,0 +1,168 @@
+use core::animation::{Animation, Keyframe};
+use core::color::Palette;
+use core::{CancelToken, Dim, Fractal};
+use egui::{widgets::*, *};
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+///Animation render running on a background thread
+struct Job {
+    frames: usize,
+    cancel: CancelToken,
+    ///Finished frames, including the ones of an earlier run
+    done: Arc<AtomicUsize>,
+    handle: JoinHandle<io::Result<usize>>,
+}
+
+///Keyframes picked from the view and the export of the animation
+pub struct AnimationExport {
+    pub keyframes: Vec<Keyframe>,
+    pub frames: usize,
+    pub size: (usize, usize),
+    pub directory: String,
+    ///Rotation of the next keyframe in degrees
+    pub rotation: f64,
+    ///Palette offset of the next keyframe
+    pub palette_offset: f32,
+    ///Result of the last finished render
+    pub status: String,
+    job: Option<Job>,
+}
+
+impl Default for AnimationExport {
+    fn default() -> Self {
+        Self {
+            keyframes: Vec::new(),
+            frames: 300,
+            size: (1920, 1080),
+            directory: "frames".to_string(),
+            rotation: 0.,
+            palette_offset: 0.,
+            status: String::new(),
+            job: None,
+        }
+    }
+}
+
+impl AnimationExport {
+    fn start(&mut self, ctx: &Context, fractal: Fractal, palette: Palette, radius: f64) {
+        let animation = Animation::new(
+            self.keyframes.clone(),
+            self.frames,
+            Dim::new(self.size.0, self.size.1),
+        )
+        .fractal(fractal)
+        .palette(palette)
+        .radius(radius);
+        let directory = PathBuf::from(&self.directory);
+
+        let cancel = CancelToken::new();
+        let done = Arc::new(AtomicUsize::new(0));
+
+        let (token, thread_done, ctx) = (cancel.clone(), done.clone(), ctx.clone());
+        let handle = thread::spawn(move || {
+            animation.render(&directory, &token, |frames| {
+                thread_done.store(frames, Ordering::Relaxed);
+                ctx.request_repaint();
+            })
+        });
+
+        self.job = Some(Job {
+            frames: self.frames,
+            cancel,
+            done,
+            handle,
+        });
+    }
+
+    ///Check whether the running render finished
+    fn poll(&mut self) {
+        let finished = matches!(&self.job, Some(job) if job.handle.is_finished());
+        if !finished {
+            return;
+        }
+
+        let job = self.job.take().unwrap();
+        self.status = match job.handle.join() {
+            Ok(Ok(rendered)) => format!("Rendered {rendered} frames"),
+            Ok(Err(err)) => format!("Failed to save frames: {err}"),
+            Err(_) => "The render crashed".to_string(),
+        };
+    }
+
+    ///Controls of the export, `view` is the keyframe of the current view.
+    ///
+    ///The animation is rendered with the fractal, palette and escape radius of the current view.
+    ///Views without a finite center and a zoom larger than zero can't become keyframes.
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        view: Keyframe,
+        fractal: Fractal,
+        palette: Palette,
+        radius: f64,
+    ) {
+        self.poll();
+
+        ui.heading("Animation");
+        ui.add(Slider::new(&mut self.rotation, -180.0..=180.0).text("Rotation"));
+        ui.add(Slider::new(&mut self.palette_offset, 0.0..=1.0).text("Palette offset"));
+
+        ui.horizontal(|ui| {
+            let valid = view.zoom > 0.
+                && view.zoom.is_finite()
+                && view.center.0.is_finite()
+                && view.center.1.is_finite();
+            if ui.add_enabled(valid, Button::new("Add keyframe")).clicked() {
+                let keyframe = view
+                    .rotation(self.rotation.to_radians())
+                    .palette_offset(self.palette_offset);
+                self.keyframes.push(keyframe);
+            }
+            if ui.button("Clear").clicked() {
+                self.keyframes.clear();
+            }
+        });
+        ui.label(format!("{} keyframes", self.keyframes.len()));
+
+        ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut self.frames).clamp_range(1..=100_000));
+            ui.label("Frames");
+        });
+        ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut self.size.0).clamp_range(16..=16_384));
+            ui.label("x");
+            ui.add(DragValue::new(&mut self.size.1).clamp_range(16..=16_384));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Directory");
+            ui.text_edit_singleline(&mut self.directory);
+        });
+
+        match &self.job {
+            Some(job) => {
+                let done = job.done.load(Ordering::Relaxed);
+                ui.add(
+                    ProgressBar::new(done as f32 / job.frames.max(1) as f32)
+                        .text(format!("{done} / {}", job.frames)),
+                );
+                if ui.button("Stop").clicked() {
+                    job.cancel.cancel();
+                }
+            }
+            None => {
+                //Frames that already exist are skipped, so this also resumes
+                let ready = !self.keyframes.is_empty();
+                if ui.add_enabled(ready, Button::new("Render")).clicked() {
+                    self.start(ui.ctx(), fractal, palette, radius);
+                }
+                if !self.status.is_empty() {
+                    ui.label(&self.status);
+                }
+            }
+        }
+    }
+}
@@ -23,7 +23,7 @@ impl Mandelbrot {
             self.handle_zoom(zoom_delta, mouse_pos.unwrap());
             println!("User zoomed...rerendering");
 
-            return self.rerender();
+            return self.rerender(ui.ctx());
         }
 
         //Did the window change size?
@@ -32,18 +32,18 @@ impl Mandelbrot {
                 "Window resized...rerendering (from {}x{} to {}x{})",
                 old_w, old_h, new_w, new_h
             );
-            return self.rerender();
+            return self.rerender(ui.ctx());
         }
 
-        //Do we have a cached image?
-        if let Some(_image) = &self.image {
+        //Do we have a cached image or is one being rendered?
+        if self.image.is_some() || self.worker.is_some() {
             //Don't rerender
             return;
         }
 
         //Need to rerender
 
-        self.rerender();
+        self.rerender(ui.ctx());
     }
 
     pub fn handle_zoom(&mut self, delta: f32, pos: Pos2) {
@@ -67,7 +67,16 @@ impl Mandelbrot {
         self.change_range(x_range, y_range);
     }
 
-    pub fn handle_drag(&mut self, delta: Vec2) {
+    ///Move the view by the whole pixels dragged so far, smaller drags are accumulated
+    pub fn handle_drag(&mut self, delta: Vec2, ctx: &Context) {
+        let total = self.drag_remainder + delta;
+        let delta = Vec2::new(total.x.trunc(), total.y.trunc());
+        self.drag_remainder = total - delta;
+
+        if delta == Vec2::ZERO {
+            return;
+        }
+
         //Relative position of the curosr, from 0-1
         let rel_x_delta = delta.x / (self.image_size.x as f32);
         let rel_y_delta = delta.y / (self.image_size.y as f32);
@@ -86,5 +95,7 @@ impl Mandelbrot {
 
         let (x_range, y_range) = self.cutout_to_range(cutout);
         self.change_range(x_range, y_range);
+
+        self.rerender_panned(delta.x as isize, delta.y as isize, ctx);
     }
 }
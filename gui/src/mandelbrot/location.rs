@@ -0,0 +1,160 @@
+use core::kf;
+use core::location::{self, Location};
+use core::parallel_png;
+use egui::{widgets::*, *};
+use std::io;
+use std::path::Path;
+
+use super::Mandelbrot;
+
+///Saving and opening locations, as png image with the location or as location file
+pub struct LocationPanel {
+    pub path: String,
+    ///Result of the last save or open
+    pub status: String,
+}
+
+impl Default for LocationPanel {
+    fn default() -> Self {
+        Self {
+            path: "mandelbrot.png".to_string(),
+            status: String::new(),
+        }
+    }
+}
+
+impl Mandelbrot {
+    ///Location of the finished image
+    pub fn image_location(&self) -> Location {
+        let mut set = self.core_set();
+        set.change_range(self.image_range.0, self.image_range.1);
+        Location::of(&set)
+    }
+
+    ///Save the finished image as png with its location
+    fn save_png(&self, path: &Path) -> io::Result<()> {
+        let cache = self
+            .cache
+            .as_ref()
+            .ok_or_else(|| io::Error::other("There is no image yet"))?;
+        parallel_png::save(path, cache, Some(&self.image_location()))
+    }
+
+    ///Save the current view and the image size as location file, Kalles Fraktaler files return
+    ///the settings they can't hold
+    fn save_location_file(&self, path: &Path) -> io::Result<Vec<String>> {
+        let size = core::Dim::new(self.image_size.x, self.image_size.y);
+        let location = Location::of(&self.core_set());
+        match kf::Kind::from_path(path) {
+            Some(kf::Kind::Location) => kf::save_location(path, &location, size),
+            Some(kf::Kind::Palette) => kf::save_palette(path, &location),
+            None => location::file::save(path, &location, Some(size)).map(|()| Vec::new()),
+        }
+    }
+
+    ///Open any location file or image, Kalles Fraktaler palettes only change the colors
+    fn open_file(&mut self, path: &Path, ctx: &Context) -> io::Result<String> {
+        let size = core::Dim::new(self.image_size.x, self.image_size.y);
+        let (status, warnings) = match kf::Kind::from_path(path) {
+            Some(kf::Kind::Location) => {
+                let (location, warnings) = kf::load_location(path, size)?;
+                (self.open_location(&location, ctx), warnings)
+            }
+            Some(kf::Kind::Palette) => {
+                let mut location = Location::of(&self.core_set());
+                let warnings = kf::load_palette(path, &mut location)?;
+                self.palette = location.palette;
+                self.palette_offset = location.palette_offset;
+                self.animation.palette_offset = location.palette_offset;
+                self.rerender(ctx);
+                ("Opened the palette".to_string(), warnings)
+            }
+            None => {
+                let (location, _) = Location::open(path)?;
+                (self.open_location(&location, ctx), Vec::new())
+            }
+        };
+        Ok(with_warnings(status, &warnings))
+    }
+
+    ///Show a location with the current image size, settings the GUI doesn't have are reported
+    pub fn open_location(&mut self, location: &Location, ctx: &Context) -> String {
+        self.fractal = location.fractal;
+        self.palette = location.palette;
+        self.palette_offset = location.palette_offset;
+        self.radius = location.radius;
+        self.max_iterations = location.iterations;
+        self.auto_iterations = None;
+
+        //Keyframes added from here keep the rotation and colors
+        self.animation.rotation = location.rotation.to_degrees();
+        self.animation.palette_offset = location.palette_offset;
+
+        let size = core::Dim::new(self.image_size.x, self.image_size.y);
+        let (x_range, y_range) = location.ranges(size);
+        self.change_range(x_range, y_range);
+        self.rerender(ctx);
+
+        let mut ignored = Vec::new();
+        if location.rotation != 0. {
+            ignored.push("rotation");
+        }
+        if location.tone_mapper != Default::default() {
+            ignored.push("tone mapping");
+        }
+        if ignored.is_empty() {
+            "Opened the location".to_string()
+        } else {
+            format!("Opened the location without {}", ignored.join(" and "))
+        }
+    }
+
+    pub fn location_ui(&mut self, ui: &mut Ui) {
+        ui.heading("Location");
+        ui.horizontal(|ui| {
+            ui.label("File");
+            ui.text_edit_singleline(&mut self.location.path)
+                .on_hover_text("png, toml, json, or Kalles Fraktaler kfr or kfp");
+        });
+
+        //Images keep the location in their metadata, anything else is a location file
+        let path = self.location.path.clone();
+        let png = path.to_lowercase().ends_with(".png");
+        ui.horizontal(|ui| {
+            //Only a finished image belongs to a single location
+            let finished = self.worker.is_none() && self.cache.is_some();
+            if ui
+                .add_enabled(finished || !png, Button::new("Save location"))
+                .clicked()
+            {
+                let saved = if png {
+                    self.save_png(Path::new(&path)).map(|()| Vec::new())
+                } else {
+                    self.save_location_file(Path::new(&path))
+                };
+                self.location.status = match saved {
+                    Ok(warnings) => with_warnings(format!("Saved {path}"), &warnings),
+                    Err(err) => format!("Failed to save: {err}"),
+                };
+            }
+            if ui.button("Open location").clicked() {
+                let ctx = ui.ctx().clone();
+                self.location.status = match self.open_file(Path::new(&path), &ctx) {
+                    Ok(status) => status,
+                    Err(err) => format!("Failed to open: {err}"),
+                };
+            }
+        });
+
+        if !self.location.status.is_empty() {
+            ui.label(&self.location.status);
+        }
+    }
+}
+
+///Status followed by one line per setting that didn't carry over
+fn with_warnings(status: String, warnings: &[String]) -> String {
+    warnings
+        .iter()
+        .fold(status, |status, warning| format!("{status}\n{warning}"))
+}
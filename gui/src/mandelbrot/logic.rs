@@ -1,9 +1,10 @@
-use core::color;
+use core::animation::DEFAULT_WIDTH;
+use core::EscapeStats;
 use egui::*;
 use egui_extras::RetainedImage;
-use rayon::prelude::*;
 use std::time::Instant;
 
+use super::worker::Worker;
 use super::Mandelbrot;
 use crate::util::*;
 
@@ -68,115 +69,127 @@ impl Mandelbrot {
         (new_x_range, new_y_range)
     }
 
-    ///Get value of the mandelbrot set according to a pixel on the screen
-    pub fn get_pixel(&self, px: f64, py: f64) -> u64 {
-        let x0 = px - (self.image_size.x / 2) as f64;
-        let y0 = py - (self.image_size.y / 2) as f64;
-
-        let x0 = (x0 / self.scale.x) + self.offset.x;
-        let y0 = (y0 / self.scale.y) + self.offset.y;
-
-        let mut x = 0.0;
-        let mut y = 0.0;
-        let mut iteration = 0_u64;
-
-        while ((x * x + y * y) <= self.radius * self.radius) && (iteration < self.max_iterations) {
-            let xtemp = x * x - y * y + x0;
-            y = 2. * x * y + y0;
-            x = xtemp;
-            iteration += 1;
-        }
-
-        /*
-        let mut x2 = 0.;
-        let mut y2 = 0.;
-
-        while (x2 + y2 <= 4. && iteration < self.max_iterations) {
-            y = 2. * x * y + y0;
-            x = x2 - y2 + x0;
-            x2 = x * x;
-            y2 = y * y;
-            iteration += 1;
-        }
-        */
-
-        iteration
-    }
-
-    ///Get a 2D Vector of colors for every single pixel on the screen Row<Column<[u8;4]>>
-    pub fn get_color_map(&self) -> Vec<Vec<[u8; 3]>> {
-        let y_range = 0..self.image_size.y;
-
-        y_range
-            .into_par_iter()
-            .map(|y| {
-                let x_range = 0..self.image_size.x;
-                x_range
-                    .into_par_iter()
-                    .map(|x| {
-                        //Get iteration count
-                        let iter = self.get_pixel(x as f64, y as f64);
-
-                        color::from_iterations(iter, color::scale::exponential)
-                    })
-                    .collect()
-            })
-            .collect()
-    }
-
-    pub fn to_binary(&self, yx_map: &Vec<Vec<[u8; 3]>>) -> Vec<u8> {
-        //Without multithreading
-        let row_length = self.image_size.x;
-        let column_height = self.image_size.y;
-        let mut data: Vec<u8> = Vec::with_capacity(row_length * column_height * 4);
-
-        for y in 0..column_height {
-            for x in 0..row_length {
-                //Get bytes
-                let bytes = yx_map[y][x];
-
-                data.push(bytes[0]);
-                data.push(bytes[1]);
-                data.push(bytes[2]);
-                data.push(255);
-            }
-        }
-
-        data
-    }
-
     pub fn write_cache_to_image(&mut self) {
         let cache = self.cache.as_ref().unwrap();
 
         let color_image = egui::ColorImage::from_rgba_unmultiplied(
-            [self.image_size.x, self.image_size.y],
-            &self.to_binary(&cache)[..],
+            [cache.width, cache.height],
+            &cache.to_rgba()[..],
         );
 
         let image = RetainedImage::from_color_image("uwu", color_image);
         self.image = Some(image);
     }
 
-    pub fn rerender(&mut self) {
-        let now = Instant::now();
+    ///Size of the default view divided by the size of the current one
+    pub fn zoom(&self) -> f64 {
+        DEFAULT_WIDTH / (self.x_range.1 - self.x_range.0)
+    }
 
-        println!("Rerendering -------------------------");
+    ///Render settings of the current view for the core library
+    pub fn core_set(&self) -> core::Mandelbrot {
+        core::Mandelbrot::from_range(
+            core::Dim::new(self.image_size.x, self.image_size.y),
+            self.x_range,
+            self.y_range,
+        )
+        .radius(self.radius)
+        .max_iterations(self.max_iterations)
+        .fractal(self.fractal)
+        .palette(self.palette)
+        .palette_offset(self.palette_offset)
+    }
 
-        let pixels = self.get_color_map();
+    ///Start rendering the current view in the background, cancels the previous render
+    pub fn rerender(&mut self, ctx: &Context) {
+        println!("Rerendering -------------------------");
         println!(
             "Image size            {} x {}",
-            pixels[0].len(),
-            pixels.len()
+            self.image_size.x, self.image_size.y
         );
 
-        let elapsed = now.elapsed();
-        println!("Calculation took      {:.2?}", elapsed);
-        let now = Instant::now();
+        if let (Some(auto), Some(stats)) = (&self.auto_iterations, &self.stats) {
+            self.max_iterations = auto.next(self.zoom(), self.max_iterations, stats);
+        }
 
-        self.cache = Some(pixels);
-        self.write_cache_to_image();
+        //Outdated as soon as the view changed
+        self.iterations = None;
+        self.panned = (0, 0);
+        self.auto_rerenders = 0;
+        self.worker = Some(Worker::spawn(self.core_set(), ctx.clone()));
+    }
+
+    ///Move the finished image by whole pixels and only calculate the exposed strips in the background.
+    ///
+    ///A pan that is still running gets cancelled, the last finished image is moved by the whole
+    ///distance instead. Falls back to a full render if there is no finished image of the same iterations.
+    pub fn rerender_panned(&mut self, dx: isize, dy: isize, ctx: &Context) {
+        //Pixels of the old image only stay valid with the same iterations
+        if let (Some(auto), Some(stats)) = (&self.auto_iterations, &self.stats) {
+            if auto.next(self.zoom(), self.max_iterations, stats) != self.max_iterations {
+                return self.rerender(ctx);
+            }
+        }
 
-        let elapsed = now.elapsed();
-        println!("Creating image took   {:.2?}\n", elapsed);
+        let iterations = match &self.iterations {
+            Some(iterations) => iterations.clone(),
+            None => return self.rerender(ctx),
+        };
+
+        self.panned = (self.panned.0 + dx, self.panned.1 + dy);
+        self.worker = Some(Worker::spawn_panned(
+            self.core_set(),
+            iterations,
+            self.panned.0,
+            self.panned.1,
+            ctx.clone(),
+        ));
+    }
+
+    ///Show the newest pass of the background render
+    pub fn receive_render(&mut self, ctx: &Context) {
+        let worker = match &self.worker {
+            Some(worker) => worker,
+            None => return,
+        };
+
+        //Check before receiving so the last pass can't be missed
+        let finished = worker.is_finished();
+
+        if let Some(pass) = worker.latest() {
+            let now = Instant::now();
+
+            self.cache = Some(pass.colors);
+            self.stats = pass
+                .iterations
+                .as_ref()
+                .map(|iterations| EscapeStats::new(iterations, worker.set.max_iterations));
+            if pass.iterations.is_some() {
+                self.panned = (0, 0);
+            }
+            self.iterations = pass.iterations;
+            self.image_range = (worker.set.x_range, worker.set.y_range);
+            self.write_cache_to_image();
+
+            let elapsed = now.elapsed();
+            println!("Creating image took   {:.2?}", elapsed);
+        }
+
+        if finished {
+            self.worker = None;
+
+            //Too many unresolved pixels, render again with more iterations
+            if let (Some(auto), Some(stats)) = (&self.auto_iterations, &self.stats) {
+                let rerenders = self.auto_rerenders;
+                if auto
+                    .rerender(self.zoom(), self.max_iterations, stats, rerenders)
+                    .is_some()
+                {
+                    println!("Too many unresolved pixels...rerendering");
+                    self.rerender(ctx);
+                    self.auto_rerenders = rerenders + 1;
+                }
+            }
+        }
     }
 }
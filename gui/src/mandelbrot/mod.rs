@@ -1,11 +1,22 @@
+use core::color::Palette;
+use core::{AutoIterations, ColorMap, EscapeStats, Fractal};
+use egui::Vec2;
 use egui_extras::RetainedImage;
 
+mod animation;
+mod interactions;
+mod location;
 mod logic;
 mod ui;
-mod interactions;
+mod worker;
+
+use animation::AnimationExport;
+use location::LocationPanel;
+use worker::Worker;
 
 use crate::util::*;
 
+///The view and settings are remembered with the `serde` feature, the images and renders are not
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "serde", serde(default))]
 pub struct Mandelbrot {
@@ -17,8 +28,43 @@ pub struct Mandelbrot {
     pub scale: Dim<f64>,
     pub radius: f64,
     pub max_iterations: u64,
-    pub cache: Option<Vec<Vec<[u8; 3]>>>,
+    pub fractal: Fractal,
+    pub palette: Palette,
+    ///Shifts the colors along the palette, from 0 to 1
+    pub palette_offset: f32,
+    ///Pick max iterations automatically instead of using the slider
+    pub auto_iterations: Option<AutoIterations>,
+    ///Renders of the current view with iterations raised by `auto_iterations`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub auto_rerenders: u32,
+    ///Statistics of the last finished image
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub stats: Option<EscapeStats>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cache: Option<ColorMap<[u8; 3]>>,
+    ///Iteration counts of the finished image, reused when panning
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub iterations: Option<ColorMap<u64>>,
+    ///Whole pixels the view moved since `iterations` were rendered
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub panned: (isize, isize),
+    ///Part of the dragged distance that is smaller than a pixel
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub drag_remainder: Vec2,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub image: Option<RetainedImage>,
+    ///X and Y range the image was rendered with, it gets reprojected onto the current view
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub image_range: ((f64, f64), (f64, f64)),
+    ///Background render of the current view
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub worker: Option<Worker>,
+    ///Keyframes and export of a zoom animation
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub animation: AnimationExport,
+    ///File the view is saved to and opened from
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub location: LocationPanel,
 }
 
 impl Default for Mandelbrot {
@@ -31,8 +77,21 @@ impl Default for Mandelbrot {
             scale: Dim { x: 0., y: 0. },
             radius: 2.,
             max_iterations: 1_000,
+            fractal: Fractal::Mandelbrot,
+            palette: Palette::Rainbow,
+            palette_offset: 0.,
+            auto_iterations: None,
+            auto_rerenders: 0,
+            stats: None,
             cache: None,
+            iterations: None,
+            panned: (0, 0),
+            drag_remainder: Vec2::ZERO,
             image: None,
+            image_range: ((-2.00, 0.47), (-1.12, 0.)),
+            worker: None,
+            animation: AnimationExport::default(),
+            location: LocationPanel::default(),
         };
         inst.calculate_offset();
         inst.calculate_scale();
@@ -1,29 +1,27 @@
 use super::Mandelbrot;
+use core::animation::Keyframe;
+use core::color::Palette;
+use core::{AutoIterations, Fractal};
 use egui::{widgets::*, *};
+use egui_extras::RetainedImage;
 
 impl Mandelbrot {
     pub fn ui(&mut self, ui: &mut Ui) {
-        //ui.ctx().request_repaint();
+        //Show the newest pass of a running render
+        self.receive_render(ui.ctx());
 
         self.handle_interactions(ui);
 
         //Show retained image
         if let Some(image) = &self.image {
             //Show cached image
-            if image
-                .show_size(
-                    ui,
-                    Vec2::new(self.image_size.x as f32, self.image_size.y as f32),
-                )
-                .hovered()
-            {
+            if self.show_image(ui, image).hovered() {
                 //Image is hoverd
                 let primary_down = ui.input().pointer.primary_down();
                 if primary_down {
                     //User dragged mouse on image
                     let delta = ui.input().pointer.delta();
-                    self.handle_drag(delta);
-                    self.rerender();
+                    self.handle_drag(delta, ui.ctx());
                 }
             }
         }
@@ -32,21 +30,79 @@ impl Mandelbrot {
             ui.vertical(|ui| self.options_ui(ui));
             ui.add_space(30.);
             ui.vertical(|ui| self.stats_ui(ui));
+            ui.add_space(30.);
+
+            let view = Keyframe::new((self.offset.x, self.offset.y), self.zoom())
+                .iterations(self.max_iterations);
+            let (fractal, palette, radius) = (self.fractal, self.palette, self.radius);
+            ui.vertical(|ui| self.animation.ui(ui, view, fractal, palette, radius));
+            ui.add_space(30.);
+            ui.vertical(|ui| self.location_ui(ui));
         });
     }
 
+    ///Paint the image where its view lies inside the current view.
+    ///
+    ///Until the current view is rendered this shows a scaled preview, e.g. right after zooming.
+    fn show_image(&self, ui: &mut Ui, image: &RetainedImage) -> Response {
+        let size = Vec2::new(self.image_size.x as f32, self.image_size.y as f32);
+        let (rect, response) = ui.allocate_exact_size(size, Sense::hover());
+
+        let (min, max) = reproject(self.image_range, (self.x_range, self.y_range));
+
+        let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.));
+        ui.painter_at(rect).add(Shape::image(
+            image.texture_id(ui.ctx()),
+            Rect::from_min_max(rect.lerp(min), rect.lerp(max)),
+            uv,
+            Color32::WHITE,
+        ));
+
+        response
+    }
+
     fn options_ui(&mut self, ui: &mut Ui) {
         ui.heading("Options");
         ui.add(Slider::new(&mut self.radius, 1.0..=10.0).text("Radius"));
-        ui.add(Slider::new(&mut self.max_iterations, 1..=40_000).text("Max iterations"));
+        ui.add_enabled(
+            self.auto_iterations.is_none(),
+            Slider::new(&mut self.max_iterations, 1..=40_000).text("Max iterations"),
+        );
+
+        let (fractal, palette) = (self.fractal, self.palette);
+        ComboBox::from_label("Fractal")
+            .selected_text(self.fractal.name())
+            .show_ui(ui, |ui| {
+                for fractal in Fractal::ALL {
+                    ui.selectable_value(&mut self.fractal, fractal, fractal.name());
+                }
+            });
+        ComboBox::from_label("Palette")
+            .selected_text(self.palette.name())
+            .show_ui(ui, |ui| {
+                for palette in Palette::ALL {
+                    ui.selectable_value(&mut self.palette, palette, palette.name());
+                }
+            });
+        let offset =
+            ui.add(Slider::new(&mut self.palette_offset, 0.0..=1.0).text("Palette offset"));
+        if fractal != self.fractal || palette != self.palette || offset.changed() {
+            self.rerender(ui.ctx());
+        }
+
+        let mut auto = self.auto_iterations.is_some();
+        if ui.checkbox(&mut auto, "Auto iterations").changed() {
+            self.auto_iterations = auto.then(AutoIterations::default);
+            self.rerender(ui.ctx());
+        }
         if ui.button("Reset zoom").clicked() {
             self.change_range((-2.00, 0.47), (-1.12, 0.));
-            self.rerender();
+            self.rerender(ui.ctx());
         }
 
         if ui.button("Force rerender").clicked() {
             println!("Forced rerender");
-            self.rerender();
+            self.rerender(ui.ctx());
         }
     }
 
@@ -65,5 +121,69 @@ impl Mandelbrot {
             "Y Range    {:.6} to {:.6}",
             self.y_range.0, self.y_range.1
         ));
+
+        ui.label(format!("Max iterations {}", self.max_iterations));
+        if let Some(stats) = &self.stats {
+            ui.label(format!("At limit   {:.2}%", stats.limit_share() * 100.));
+            ui.label(format!("Unresolved {:.3}%", stats.unresolved() * 100.));
+        }
+    }
+}
+
+type Range = ((f64, f64), (f64, f64));
+
+///Corners of the image relative to the current view, from 0-1 if they are visible
+fn reproject(((x_min, x_max), (y_min, y_max)): Range, (x_range, y_range): Range) -> (Vec2, Vec2) {
+    let width = x_range.1 - x_range.0;
+    let height = y_range.1 - y_range.0;
+
+    let min = Vec2::new(
+        ((x_min - x_range.0) / width) as f32,
+        ((y_min - y_range.0) / height) as f32,
+    );
+    let max = Vec2::new(
+        ((x_max - x_range.0) / width) as f32,
+        ((y_max - y_range.0) / height) as f32,
+    );
+
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEW: Range = ((-2., 0.5), (-1., 1.));
+
+    #[test]
+    fn same_view_fills_the_image() {
+        assert_eq!(reproject(VIEW, VIEW), (Vec2::ZERO, Vec2::new(1., 1.)));
+    }
+
+    #[test]
+    fn zooming_in_scales_around_the_center() {
+        let zoomed = ((-1.375, -0.125), (-0.5, 0.5));
+        assert_eq!(
+            reproject(VIEW, zoomed),
+            (Vec2::new(-0.5, -0.5), Vec2::new(1.5, 1.5))
+        );
+        assert_eq!(
+            reproject(zoomed, VIEW),
+            (Vec2::new(0.25, 0.25), Vec2::new(0.75, 0.75))
+        );
+    }
+
+    #[test]
+    fn default_view_has_zoom_one() {
+        assert!((Mandelbrot::default().zoom() - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn panning_moves_the_image() {
+        let panned = ((-1.5, 1.), (-1.5, 0.5));
+        assert_eq!(
+            reproject(VIEW, panned),
+            (Vec2::new(-0.2, 0.25), Vec2::new(0.8, 1.25))
+        );
     }
 }
@@ -0,0 +1,110 @@
+use core::{CancelToken, ColorMap, Progressive};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+///Finished pass of a render
+pub struct Pass {
+    pub colors: ColorMap<[u8; 3]>,
+    ///Only sent with the last pass, earlier passes are just upscaled
+    pub iterations: Option<ColorMap<u64>>,
+}
+
+///Progressive render running on a background thread
+pub struct Worker {
+    ///Settings the image is rendered with
+    pub set: core::Mandelbrot,
+    cancel: CancelToken,
+    receiver: Receiver<Pass>,
+    handle: JoinHandle<()>,
+}
+
+impl Worker {
+    ///Start rendering, the context gets a repaint request for every finished pass
+    pub fn spawn(set: core::Mandelbrot, ctx: egui::Context) -> Self {
+        Self::start(set, move |set, token, sender| {
+            let mut progressive = Progressive::new(set).cancel_token(token.clone());
+
+            let mut now = Instant::now();
+            while let Some(step) = progressive.next_pass() {
+                let pass = Pass {
+                    colors: progressive.color_map(),
+                    iterations: progressive.is_done().then(|| progressive.iterations()),
+                };
+
+                //Don't show passes of an outdated view
+                if token.is_cancelled() || sender.send(pass).is_err() {
+                    return;
+                }
+                ctx.request_repaint();
+
+                let elapsed = now.elapsed();
+                println!("Pass 1/{:<2} took       {:.2?}", step, elapsed);
+                now = Instant::now();
+            }
+        })
+    }
+
+    ///Move the finished image of the previous view by whole pixels and only calculate the exposed strips
+    pub fn spawn_panned(
+        set: core::Mandelbrot,
+        mut iterations: ColorMap<u64>,
+        dx: isize,
+        dy: isize,
+        ctx: egui::Context,
+    ) -> Self {
+        Self::start(set, move |set, token, sender| {
+            let now = Instant::now();
+            if !set.pan(&mut iterations, dx, dy, &token) {
+                return;
+            }
+
+            let pass = Pass {
+                colors: iterations.map(|&iter| set.color(iter)),
+                iterations: Some(iterations),
+            };
+            if token.is_cancelled() || sender.send(pass).is_err() {
+                return;
+            }
+            ctx.request_repaint();
+
+            let elapsed = now.elapsed();
+            println!("Panning took          {:.2?}", elapsed);
+        })
+    }
+
+    ///Run a render on its own thread, it sends its passes and stops once the token is cancelled
+    fn start(
+        set: core::Mandelbrot,
+        render: impl FnOnce(core::Mandelbrot, CancelToken, Sender<Pass>) + Send + 'static,
+    ) -> Self {
+        let cancel = CancelToken::new();
+        let (sender, receiver) = mpsc::channel();
+
+        let token = cancel.clone();
+        let thread_set = set.clone();
+        let handle = thread::spawn(move || render(thread_set, token, sender));
+
+        Self {
+            set,
+            cancel,
+            receiver,
+            handle,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    ///Newest pass that arrived since the last call
+    pub fn latest(&self) -> Option<Pass> {
+        self.receiver.try_iter().last()
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
@@ -2,7 +2,7 @@
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "serde", serde(default))]
 ///2 Dimensions
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Dim<T> {
     pub x: T,
     pub y: T,
@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: tile-server [OPTIONS]
+
+Serves tiles at /{fractal}/{z}/{x}/{y}.png?palette=<NAME>&iterations=<N>
+
+Options:
+  -b, --bind <ADDR>          Address to listen on, use 0.0.0.0:<PORT> for the whole network [default: 127.0.0.1:8080]
+      --tile-size <N>        Tile size in pixels, at most 4096 [default: 256]
+      --cache <N>            Tiles kept in memory [default: 4096]
+      --disk-cache <DIR>     Directory that keeps every rendered tile
+  -h, --help                 Print this help";
+
+///Largest tile size, the deepest zoom level is this size shifted by the zoom
+pub const MAX_TILE_SIZE: usize = 4096;
+
+pub struct Args {
+    pub bind: String,
+    pub tile_size: usize,
+    pub cache: usize,
+    pub disk_cache: Option<PathBuf>,
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, name: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid {name} '{value}'"))
+}
+
+impl Args {
+    ///Parse the arguments without the program name, `Err` contains the message for the user
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut bind = "127.0.0.1:8080".to_string();
+        let mut tile_size = 256;
+        let mut cache = 4096;
+        let mut disk_cache = None;
+
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(String::new());
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for '{arg}'"))?;
+
+            match arg.as_str() {
+                "-b" | "--bind" => bind = value,
+                "--tile-size" => tile_size = parse_number(&value, "tile size")?,
+                "--cache" => cache = parse_number(&value, "cache size")?,
+                "--disk-cache" => disk_cache = Some(PathBuf::from(value)),
+                _ => return Err(format!("Unknown argument '{arg}'")),
+            }
+        }
+
+        if tile_size == 0 {
+            return Err("Tile size can't be zero".to_string());
+        }
+        if tile_size > MAX_TILE_SIZE {
+            return Err(format!("Tile size can't be larger than {MAX_TILE_SIZE}"));
+        }
+
+        Ok(Self {
+            bind,
+            tile_size,
+            cache,
+            disk_cache,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn limits_the_tile_size() {
+        assert_eq!(parse(&["--tile-size", "512"]).unwrap().tile_size, 512);
+        assert_eq!(parse(&["--tile-size", "4096"]).unwrap().tile_size, 4096);
+        assert!(parse(&["--tile-size", "0"]).is_err());
+        assert!(parse(&["--tile-size", "4097"]).is_err());
+        assert!(parse(&["--tile-size", "18446744073709551615"]).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use core::color::Palette;
+use core::Fractal;
+
+///Everything that changes the content of a tile
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileKey {
+    pub fractal: Fractal,
+    pub palette: Palette,
+    pub iterations: u64,
+    pub z: u32,
+    pub x: usize,
+    pub y: usize,
+}
+
+impl TileKey {
+    ///Path of the tile inside the disk cache
+    fn path(&self) -> PathBuf {
+        [
+            self.fractal.name().to_string(),
+            self.palette.name().to_string(),
+            self.iterations.to_string(),
+            self.z.to_string(),
+            self.x.to_string(),
+            format!("{}.png", self.y),
+        ]
+        .iter()
+        .collect()
+    }
+}
+
+///Encoded tiles in memory, the least recently used one is dropped when it is full
+struct Lru {
+    capacity: usize,
+    ///Tile and the time it was last used
+    tiles: HashMap<TileKey, (Vec<u8>, u64)>,
+    time: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &TileKey) -> Option<Vec<u8>> {
+        self.time += 1;
+        let (png, used) = self.tiles.get_mut(key)?;
+        *used = self.time;
+        Some(png.clone())
+    }
+
+    fn insert(&mut self, key: TileKey, png: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.tiles.len() >= self.capacity && !self.tiles.contains_key(&key) {
+            let oldest = self
+                .tiles
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.tiles.remove(&oldest);
+            }
+        }
+
+        self.time += 1;
+        self.tiles.insert(key, (png, self.time));
+    }
+}
+
+///Memory cache in front of an optional disk cache
+pub struct TileCache {
+    memory: Mutex<Lru>,
+    disk: Option<PathBuf>,
+}
+
+impl TileCache {
+    pub fn new(capacity: usize, disk: Option<PathBuf>) -> Self {
+        Self {
+            memory: Mutex::new(Lru {
+                capacity,
+                tiles: HashMap::with_capacity(capacity),
+                time: 0,
+            }),
+            disk,
+        }
+    }
+
+    ///Cached png of a tile, tiles from the disk are moved into memory
+    pub fn get(&self, key: &TileKey) -> Option<Vec<u8>> {
+        if let Some(png) = self.memory.lock().unwrap().get(key) {
+            return Some(png);
+        }
+
+        let png = fs::read(self.disk.as_ref()?.join(key.path())).ok()?;
+        self.memory.lock().unwrap().insert(*key, png.clone());
+        Some(png)
+    }
+
+    ///Keep a rendered tile in memory and on the disk
+    pub fn insert(&self, key: TileKey, png: Vec<u8>) -> io::Result<()> {
+        if let Some(disk) = &self.disk {
+            let path = disk.join(key.path());
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            //Rename at the end, so other requests never read half a file.
+            //Every writer gets its own file, requests for the same tile may render it at the same time.
+            static WRITES: AtomicUsize = AtomicUsize::new(0);
+            let write = WRITES.fetch_add(1, Ordering::Relaxed);
+            let temporary = path.with_extension(format!("png.{}-{write}.part", std::process::id()));
+            fs::write(&temporary, &png)?;
+            fs::rename(&temporary, &path)?;
+        }
+
+        self.memory.lock().unwrap().insert(key, png);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(x: usize) -> TileKey {
+        TileKey {
+            fractal: Fractal::Mandelbrot,
+            palette: Palette::Fire,
+            iterations: 100,
+            z: 4,
+            x,
+            y: 2,
+        }
+    }
+
+    #[test]
+    fn drops_the_least_recently_used_tile() {
+        let cache = TileCache::new(2, None);
+        cache.insert(key(0), vec![0]).unwrap();
+        cache.insert(key(1), vec![1]).unwrap();
+        assert_eq!(cache.get(&key(0)), Some(vec![0]));
+
+        cache.insert(key(2), vec![2]).unwrap();
+        assert_eq!(cache.get(&key(1)), None);
+        assert_eq!(cache.get(&key(0)), Some(vec![0]));
+        assert_eq!(cache.get(&key(2)), Some(vec![2]));
+    }
+
+    #[test]
+    fn nothing_is_kept_without_capacity() {
+        let cache = TileCache::new(0, None);
+        cache.insert(key(0), vec![0]).unwrap();
+        assert_eq!(cache.get(&key(0)), None);
+    }
+
+    #[test]
+    fn disk_cache_survives_restarts() {
+        let dir =
+            std::env::temp_dir().join(format!("mandelbrot-tile-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        TileCache::new(1, Some(dir.clone()))
+            .insert(key(3), vec![3])
+            .unwrap();
+        assert!(dir.join("mandelbrot/fire/100/4/3/2.png").exists());
+
+        let cache = TileCache::new(1, Some(dir.clone()));
+        assert_eq!(cache.get(&key(3)), Some(vec![3]));
+        assert_eq!(cache.get(&key(4)), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writers_of_the_same_tile_dont_collide() {
+        let dir =
+            std::env::temp_dir().join(format!("mandelbrot-tile-writers-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = TileCache::new(0, Some(dir.clone()));
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..20 {
+                        cache.insert(key(5), vec![5; 1000]).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(cache.get(&key(5)), Some(vec![5; 1000]));
+        let files = fs::read_dir(dir.join("mandelbrot/fire/100/4/5")).unwrap();
+        assert_eq!(files.count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use core::color::Palette;
+use core::tiles::{Layout, Pyramid};
+use core::{png_crate, AutoIterations, Dim, Fractal, Mandelbrot};
+use std::sync::Arc;
+use std::time::Instant;
+use std::{env, process, thread};
+use tiny_http::{Header, Request, Response, Server};
+
+mod args;
+mod cache;
+
+use args::{Args, USAGE};
+use cache::{TileCache, TileKey};
+
+///Deepest zoom level, pixels get too small for f64 after that
+const MAX_ZOOM: u32 = 40;
+
+///Center and size of the square that zoom level 0 shows
+fn world(fractal: Fractal) -> ((f64, f64), f64) {
+    match fractal {
+        Fractal::Mandelbrot => ((-0.765, 0.), 2.5),
+        Fractal::BurningShip => ((-0.4, -0.6), 3.5),
+    }
+}
+
+///Tile that a request asks for, `Err` contains the status code and the message
+fn parse_request(url: &str) -> Result<TileKey, (u16, String)> {
+    let not_found = || (404, format!("No tile at '{url}'"));
+
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let [fractal, z, x, y] = parts[..] else {
+        return Err(not_found());
+    };
+
+    let fractal = Fractal::from_name(fractal).ok_or_else(not_found)?;
+    let y = y.strip_suffix(".png").ok_or_else(not_found)?;
+    let (z, x, y): (u32, usize, usize) = match (z.parse(), x.parse(), y.parse()) {
+        (Ok(z), Ok(x), Ok(y)) => (z, x, y),
+        _ => return Err(not_found()),
+    };
+    if z > MAX_ZOOM || x >> z > 0 || y >> z > 0 {
+        return Err(not_found());
+    }
+
+    let auto = AutoIterations::default();
+    let mut palette = Palette::Rainbow;
+    let mut iterations = auto.minimum((1_u64 << z) as f64);
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match name {
+            "palette" => {
+                palette = Palette::from_name(value)
+                    .ok_or_else(|| (400, format!("Unknown palette '{value}'")))?
+            }
+            "iterations" => {
+                iterations = value
+                    .parse::<u64>()
+                    .map_err(|_| (400, format!("Invalid iterations '{value}'")))?
+                    .clamp(1, auto.max)
+            }
+            _ => return Err((400, format!("Unknown parameter '{name}'"))),
+        }
+    }
+
+    Ok(TileKey {
+        fractal,
+        palette,
+        iterations,
+        z,
+        x,
+        y,
+    })
+}
+
+fn render(key: &TileKey, tile_size: usize) -> Vec<u8> {
+    let ((x, y), size) = world(key.fractal);
+    let pixels = tile_size
+        .checked_mul(1 << MAX_ZOOM)
+        .expect("Tile size is limited by the arguments");
+
+    let set = Mandelbrot::from_range(
+        Dim::new(pixels, pixels),
+        (x - size / 2., x + size / 2.),
+        (y - size / 2., y + size / 2.),
+    )
+    .max_iterations(key.iterations)
+    .fractal(key.fractal)
+    .palette(key.palette);
+
+    let pyramid = Pyramid::new(set).tile_size(tile_size).layout(Layout::Xyz);
+
+    let mut png = Vec::new();
+    png_crate::write(&mut png, &pyramid.tile(key.z, key.x, key.y))
+        .expect("Encoding into memory can't fail");
+    png
+}
+
+fn handle(request: Request, cache: &TileCache, tile_size: usize) {
+    let header = |name: &str, value: &str| Header::from_bytes(name, value).unwrap();
+
+    let key = match parse_request(request.url()) {
+        Ok(key) => key,
+        Err((status, message)) => {
+            let response = Response::from_string(message).with_status_code(status);
+            let _ = request.respond(response);
+            return;
+        }
+    };
+
+    let png = match cache.get(&key) {
+        Some(png) => png,
+        None => {
+            let now = Instant::now();
+            let png = render(&key, tile_size);
+            eprintln!("Rendered {} in {:.2?}", request.url(), now.elapsed());
+
+            if let Err(err) = cache.insert(key, png.clone()) {
+                eprintln!("Failed to cache {}: {err}", request.url());
+            }
+            png
+        }
+    };
+
+    let response = Response::from_data(png)
+        .with_header(header("Content-Type", "image/png"))
+        .with_header(header("Cache-Control", "public, max-age=86400"))
+        //Viewers on other origins may use the tiles
+        .with_header(header("Access-Control-Allow-Origin", "*"));
+    let _ = request.respond(response);
+}
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{message}\n");
+            }
+            eprintln!("{USAGE}");
+            process::exit(1);
+        }
+    };
+
+    let server = match Server::http(&args.bind) {
+        Ok(server) => Arc::new(server),
+        Err(err) => {
+            eprintln!("Failed to listen on {}: {err}", args.bind);
+            process::exit(1);
+        }
+    };
+    let cache = Arc::new(TileCache::new(args.cache, args.disk_cache));
+
+    eprintln!(
+        "Serving tiles at http://{}/{{fractal}}/{{z}}/{{x}}/{{y}}.png",
+        args.bind
+    );
+
+    //Every thread waits for its own requests, the tiles are rendered on the rayon pool
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let (server, cache) = (server.clone(), cache.clone());
+            let tile_size = args.tile_size;
+
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, &cache, tile_size);
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tiles() {
+        let key = parse_request("/burning-ship/3/7/0.png").unwrap();
+        assert_eq!(key.fractal, Fractal::BurningShip);
+        assert_eq!((key.z, key.x, key.y), (3, 7, 0));
+        assert_eq!(key.palette, Palette::Rainbow);
+        assert_eq!(key.iterations, AutoIterations::default().minimum(8.));
+
+        let key = parse_request("/mandelbrot/0/0/0.png?palette=fire&iterations=5000").unwrap();
+        assert_eq!((key.palette, key.iterations), (Palette::Fire, 5000));
+    }
+
+    #[test]
+    fn rejects_missing_tiles() {
+        for url in [
+            "/",
+            "/julia/0/0/0.png",
+            "/mandelbrot/0/0/0",
+            "/mandelbrot/0/0/0.jpg",
+            "/mandelbrot/2/4/0.png",
+            "/mandelbrot/41/0/0.png",
+            "/mandelbrot/1/0/0/0.png",
+        ] {
+            assert_eq!(parse_request(url).unwrap_err().0, 404, "{url}");
+        }
+
+        for query in ["palette=blue", "iterations=many", "size=2"] {
+            let url = format!("/mandelbrot/0/0/0.png?{query}");
+            assert_eq!(parse_request(&url).unwrap_err().0, 400, "{url}");
+        }
+    }
+
+    #[test]
+    fn renders_pngs_of_the_tile_size() {
+        let key = parse_request("/mandelbrot/1/1/0.png?iterations=50").unwrap();
+        let png = render(&key, 16);
+
+        //Signature and the size at the start of the header chunk
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(png[16..24], [0, 0, 0, 16, 0, 0, 0, 16]);
+    }
+}